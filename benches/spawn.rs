@@ -0,0 +1,27 @@
+use std::env;
+use std::time::Instant;
+
+use oxide::{Shell, ShellCommand};
+
+// Measures how many tiny external commands the executor can run per second.
+// Usage: cargo bench --bench spawn [iterations]
+fn main() {
+    let iterations: u32 = env::args()
+        .skip(1)
+        .find_map(|a| a.parse().ok())
+        .unwrap_or(1000);
+
+    let mut shell = Shell::new();
+    let tokens = ["true"];
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        ShellCommand::parse(&tokens).execute(&mut shell);
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{iterations} commands in {elapsed:.2?} ({:.0} commands/sec)",
+        f64::from(iterations) / elapsed.as_secs_f64()
+    );
+}
@@ -0,0 +1,59 @@
+use std::collections::BTreeMap;
+
+use crate::{Token, command_starts, tokenize};
+
+// Aliases: a word where a command starts that names one is replaced by
+// the tokens of its value. An alias isn't expanded again inside its own
+// value, so `alias ls='ls -F'` works, and one whose value ends in a blank
+// has the word after it checked as well.
+pub fn expand(tokens: Vec<Token>, aliases: &BTreeMap<String, String>) -> Vec<Token> {
+    if aliases.is_empty() {
+        return tokens;
+    }
+    let mut out = Vec::with_capacity(tokens.len());
+    let mut next_too = false;
+    for token in tokens {
+        next_too = push(&mut out, token, aliases, &mut Vec::new(), next_too);
+    }
+    out
+}
+
+// How many aliases can expand inside one another before the rest are
+// left as words.
+const ALIAS_DEPTH: usize = 64;
+
+// Adds `token` to `out`, or what it expands to if it's an alias being
+// used as a command and isn't among those `expanding` already. Returns
+// whether the word after it should be checked too.
+fn push(
+    out: &mut Vec<Token>,
+    token: Token,
+    aliases: &BTreeMap<String, String>,
+    expanding: &mut Vec<String>,
+    next_too: bool,
+) -> bool {
+    let at_start = next_too || command_starts(out, out.len());
+    let value = match &token {
+        Token::Word(word)
+            if at_start && !expanding.contains(word) && expanding.len() < ALIAS_DEPTH =>
+        {
+            aliases.get(word)
+        }
+        _ => None,
+    };
+    let Some(value) = value else {
+        out.push(token);
+        return false;
+    };
+    let Token::Word(name) = token else {
+        unreachable!("only words are aliases");
+    };
+
+    expanding.push(name);
+    let mut last_too = false;
+    for token in tokenize(value) {
+        last_too = push(out, token, aliases, expanding, last_too);
+    }
+    expanding.pop();
+    last_too || value.ends_with([' ', '\t'])
+}
@@ -0,0 +1,295 @@
+use crate::vars::Variables;
+
+// Arithmetic for `$(( ))`, `(( ))` and `let`: integers only, with C's
+// operators and precedence for the ones supported. A variable is read as a
+// number, and one that isn't set or is empty counts as 0. `=`, `+=` and the
+// like, and `++` and `--` before or after a name, assign to it.
+pub fn eval(expr: &str, vars: &mut Variables) -> Result<i64, String> {
+    let tokens = lex(expr).map_err(|e| format!("{}: {e}", expr.trim()))?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        vars,
+    };
+    parser
+        .expression()
+        .and_then(|value| match parser.peek() {
+            None => Ok(value),
+            Some(token) => Err(format!(
+                "syntax error in expression (error token is \"{token}\")"
+            )),
+        })
+        .map_err(|e| format!("{}: {e}", expr.trim()))
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{n}"),
+            Token::Name(name) => f.write_str(name),
+            Token::Op(op) => f.write_str(op),
+        }
+    }
+}
+
+// longest first, so `<=` isn't read as `<` then `=`
+const OPS: &[&str] = &[
+    "&&", "||", "==", "!=", "<=", ">=", "++", "--", "+=", "-=", "*=", "/=", "%=", "<", ">", "=",
+    "+", "-", "*", "/", "%", "!", "(", ")",
+];
+
+const ASSIGNMENTS: &[&str] = &["=", "+=", "-=", "*=", "/=", "%="];
+
+fn lex(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expr;
+    loop {
+        rest = rest.trim_start();
+        let Some(c) = rest.chars().next() else {
+            return Ok(tokens);
+        };
+        if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(number(&rest[..end])?));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if let Some(&op) = OPS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(format!(
+                "syntax error: invalid arithmetic operator (error token is \"{rest}\")"
+            ));
+        }
+    }
+}
+
+// A decimal number, or hexadecimal with 0x.
+fn number(text: &str) -> Result<i64, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("{text}: value too great for base (error token is \"{text}\")"))
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    vars: &'a mut Variables,
+}
+
+// Binary operators by precedence, loosest first.
+const LEVELS: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn take_op(&mut self, ops: &[&str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                let op = *op;
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn expression(&mut self) -> Result<i64, String> {
+        self.assignment()
+    }
+
+    // Assignments group to the right, so `a = b = 1` sets both.
+    fn assignment(&mut self) -> Result<i64, String> {
+        let (Some(Token::Name(name)), Some(Token::Op(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+        else {
+            return self.binary(0);
+        };
+        if !ASSIGNMENTS.contains(op) {
+            return self.binary(0);
+        }
+        let (name, op) = (name.clone(), *op);
+        self.pos += 2;
+        let value = self.assignment()?;
+        let value = match op.strip_suffix('=') {
+            Some("") | None => value,
+            Some(op) => apply(op, variable(self.vars, &name)?, value)?,
+        };
+        self.vars.set(&name, &value.to_string());
+        Ok(value)
+    }
+
+    // `++name` or `--name` adds or takes 1 and gives the new value; after
+    // the name, they give the old one.
+    fn step(&mut self, name: &str, op: &str, prefix: bool) -> Result<i64, String> {
+        let old = variable(self.vars, name)?;
+        let new = if op == "++" {
+            old.wrapping_add(1)
+        } else {
+            old.wrapping_sub(1)
+        };
+        self.vars.set(name, &new.to_string());
+        Ok(if prefix { new } else { old })
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        let Some(ops) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut left = self.binary(level + 1)?;
+        while let Some(op) = self.take_op(ops) {
+            let right = self.binary(level + 1)?;
+            left = apply(op, left, right)?;
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        if let Some(op) = self.take_op(&["++", "--"]) {
+            return match self.next() {
+                Some(Token::Name(name)) => self.step(&name, op, true),
+                _ => Err(format!(
+                    "syntax error: operand expected (error token is \"{op}\")"
+                )),
+            };
+        }
+        match self.take_op(&["-", "+", "!"]) {
+            Some("-") => Ok(self.unary()?.wrapping_neg()),
+            Some("!") => Ok((self.unary()? == 0) as i64),
+            Some(_) => self.unary(),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(n),
+            Some(Token::Name(name)) => match self.take_op(&["++", "--"]) {
+                Some(op) => self.step(&name, op, false),
+                None => variable(self.vars, &name),
+            },
+            Some(Token::Op("(")) => {
+                let value = self.expression()?;
+                match self.next() {
+                    Some(Token::Op(")")) => Ok(value),
+                    _ => Err("missing `)'".to_string()),
+                }
+            }
+            Some(token) => Err(format!(
+                "syntax error: operand expected (error token is \"{token}\")"
+            )),
+            None => Err("syntax error: operand expected".to_string()),
+        }
+    }
+}
+
+fn apply(op: &str, left: i64, right: i64) -> Result<i64, String> {
+    Ok(match op {
+        "||" => (left != 0 || right != 0) as i64,
+        "&&" => (left != 0 && right != 0) as i64,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        "<" => (left < right) as i64,
+        "<=" => (left <= right) as i64,
+        ">" => (left > right) as i64,
+        ">=" => (left >= right) as i64,
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Err("division by 0".to_string()),
+        "/" => left.wrapping_div(right),
+        "%" => left.wrapping_rem(right),
+        _ => unreachable!("not a binary operator: {op}"),
+    })
+}
+
+fn variable(vars: &Variables, name: &str) -> Result<i64, String> {
+    let value = vars.get(name).unwrap_or_default();
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(0);
+    }
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+    match digits.chars().next() {
+        Some(c) if c.is_ascii_digit() => {
+            let n = number(digits)?;
+            Ok(if negative { n.wrapping_neg() } else { n })
+        }
+        _ => Err(format!("{value}: not a number")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calc(expr: &str) -> Result<i64, String> {
+        eval(expr, &mut Variables::default())
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(calc("1 + 2 * 3"), Ok(7));
+        assert_eq!(calc("(1 + 2) * 3"), Ok(9));
+        assert_eq!(calc("10 - 4 - 3"), Ok(3));
+        assert_eq!(calc("7 % 4 + -2"), Ok(1));
+        assert_eq!(calc("1 < 2 && 2 < 1 || 3 == 3"), Ok(1));
+        assert_eq!(calc("!0 + !5"), Ok(1));
+        assert_eq!(calc("0x1f"), Ok(31));
+    }
+
+    #[test]
+    fn variables() {
+        let mut vars = Variables::default();
+        assert_eq!(eval("arith_test_x = 5", &mut vars), Ok(5));
+        assert_eq!(eval("arith_test_x += 2", &mut vars), Ok(7));
+        assert_eq!(eval("arith_test_x++", &mut vars), Ok(7));
+        assert_eq!(eval("++arith_test_x", &mut vars), Ok(9));
+        assert_eq!(eval("arith_test_x * 2", &mut vars), Ok(18));
+        assert_eq!(vars.get("arith_test_x").as_deref(), Some("9"));
+        // unset counts as 0
+        assert_eq!(eval("arith_test_unset + 1", &mut vars), Ok(1));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(calc("1 / 0").is_err());
+        assert!(calc("1 +").is_err());
+        assert!(calc("1 2").is_err());
+        assert!(calc("2 $ 3").is_err());
+    }
+}
@@ -0,0 +1,164 @@
+// Brace expansion, which turns one word into several before anything else
+// is expanded: `src/{bin,lib}` is `src/bin src/lib`, and `{1..5}` and
+// `{a..e}` are sequences, with an optional step as in `{0..100..10}`.
+// Braces nest, and more than one in a word multiply out.
+
+type Chars = Vec<(char, bool)>;
+
+// The words `word` expands to. `active` are the byte offsets of the `{`,
+// `,` and `}` the tokenizer found unquoted; any others are literal.
+pub fn expand(word: &str, active: &[usize]) -> Vec<String> {
+    let chars: Chars = word
+        .char_indices()
+        .map(|(i, c)| (c, active.contains(&i)))
+        .collect();
+    let mut words = Vec::new();
+    expand_chars(&chars, &mut words);
+    words
+}
+
+fn expand_chars(chars: &[(char, bool)], words: &mut Vec<String>) {
+    let Some((open, close, alternatives)) = find(chars) else {
+        words.push(chars.iter().map(|&(c, _)| c).collect());
+        return;
+    };
+    for alternative in alternatives {
+        let mut word = chars[..open].to_vec();
+        word.extend(alternative);
+        word.extend_from_slice(&chars[close + 1..]);
+        expand_chars(&word, words);
+    }
+}
+
+// The first brace expression in the word: where it opens and closes, and
+// what goes in its place each time. A `{` with no `,` or sequence inside
+// is left as it is.
+fn find(chars: &[(char, bool)]) -> Option<(usize, usize, Vec<Chars>)> {
+    for open in 0..chars.len() {
+        if chars[open] != ('{', true) {
+            continue;
+        }
+        let mut depth = 0;
+        let mut commas = Vec::new();
+        let mut close = None;
+        for (i, &item) in chars.iter().enumerate().skip(open + 1) {
+            match item {
+                ('{', true) => depth += 1,
+                ('}', true) if depth == 0 => {
+                    close = Some(i);
+                    break;
+                }
+                ('}', true) => depth -= 1,
+                (',', true) if depth == 0 => commas.push(i),
+                _ => {}
+            }
+        }
+        let Some(close) = close else {
+            continue;
+        };
+
+        if !commas.is_empty() {
+            let mut alternatives = Vec::new();
+            let mut start = open + 1;
+            for end in commas.into_iter().chain([close]) {
+                alternatives.push(chars[start..end].to_vec());
+                start = end + 1;
+            }
+            return Some((open, close, alternatives));
+        }
+        let inner: String = chars[open + 1..close].iter().map(|&(c, _)| c).collect();
+        if let Some(items) = sequence(&inner) {
+            let alternatives = items
+                .into_iter()
+                .map(|item| item.chars().map(|c| (c, false)).collect())
+                .collect();
+            return Some((open, close, alternatives));
+        }
+    }
+    None
+}
+
+// `a..b` or `a..b..step`, between two integers or two letters. Integers
+// written with leading zeros are padded to the same width.
+fn sequence(inner: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = inner.split("..").collect();
+    let (start, end, step) = match parts.as_slice() {
+        [start, end] => (*start, *end, 1),
+        [start, end, step] => (*start, *end, step.parse::<i64>().ok()?),
+        _ => return None,
+    };
+    let step = step.unsigned_abs().max(1) as usize;
+
+    if let (Ok(a), Ok(b)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let padded = |s: &str| {
+            s.trim_start_matches('-').len() > 1 && s.trim_start_matches('-').starts_with('0')
+        };
+        let width = if padded(start) || padded(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        let values: Vec<i64> = if a <= b {
+            (a..=b).step_by(step).collect()
+        } else {
+            (b..=a).rev().step_by(step).collect()
+        };
+        return Some(values.into_iter().map(|n| format!("{n:0width$}")).collect());
+    }
+
+    let letter = |s: &str| {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphabetic() => Some(c),
+            _ => None,
+        }
+    };
+    let (a, b) = (letter(start)?, letter(end)?);
+    let values: Vec<char> = if a <= b {
+        (a..=b).step_by(step).collect()
+    } else {
+        (b..=a).rev().step_by(step).collect()
+    };
+    Some(values.into_iter().map(String::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The word with every brace and comma in it active.
+    fn all(word: &str) -> Vec<String> {
+        let active: Vec<usize> = word
+            .char_indices()
+            .filter(|&(_, c)| matches!(c, '{' | ',' | '}'))
+            .map(|(i, _)| i)
+            .collect();
+        expand(word, &active)
+    }
+
+    #[test]
+    fn alternatives() {
+        assert_eq!(all("src/{bin,lib}"), ["src/bin", "src/lib"]);
+        assert_eq!(all("{a,b}{1,2}"), ["a1", "a2", "b1", "b2"]);
+        assert_eq!(all("a{b,{c,d}}e"), ["abe", "ace", "ade"]);
+        assert_eq!(all("{,x}"), ["", "x"]);
+    }
+
+    #[test]
+    fn sequences() {
+        assert_eq!(all("{1..4}"), ["1", "2", "3", "4"]);
+        assert_eq!(all("{3..1}"), ["3", "2", "1"]);
+        assert_eq!(all("{0..10..5}"), ["0", "5", "10"]);
+        assert_eq!(all("{08..10}"), ["08", "09", "10"]);
+        assert_eq!(all("{a..c}"), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn left_alone() {
+        assert_eq!(all("{}"), ["{}"]);
+        assert_eq!(all("{a}"), ["{a}"]);
+        assert_eq!(all("{a,b"), ["{a,b"]);
+        // quoted, so none of it is active
+        assert_eq!(expand("{a,b}", &[]), ["{a,b}"]);
+    }
+}
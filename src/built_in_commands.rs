@@ -1,3 +1,1051 @@
+use std::fs;
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::arith;
+use crate::completion;
+use crate::env_file;
+use crate::function_env;
+use crate::signals;
+use crate::terminal;
+use crate::traps;
+use crate::vars;
+use crate::{ExecutionContext, LoopControl, Shell, ShellOptions, error_text, wait_foreground};
+
+// Status returned by `wait -t` when the timeout expires, matching timeout(1).
+const WAIT_TIMEOUT: i32 = 124;
+
+pub const BUILTINS: &[&str] = &[
+    "alias", "bg", "break", "cd", "compgen", "complete", "continue", "disown", "dotenv", "echo",
+    "exec", "exit", "export", "fg", "jobs", "kill", "let", "local", "pwd", "return", "set",
+    "shift", "trap", "type", "unalias", "unset", "wait",
+];
+
 pub fn is_builtin(cmd: &str) -> bool {
-    matches!(cmd, "exit" | "echo" | "pwd" | "type" | "cd")
+    BUILTINS.contains(&cmd)
+}
+
+pub fn compgen(args: &[&str], ctx: &mut ExecutionContext) -> io::Result<i32> {
+    let parsed = match completion::parse_spec_args(args) {
+        Ok(parsed) if !parsed.print && !parsed.remove => parsed,
+        Ok(_) => {
+            ctx.error(
+                "compgen: usage: compgen [-bcdefuv] [-A action] [-W wordlist] [-P prefix] [-S suffix] [word]",
+            );
+            return Ok(2);
+        }
+        Err(e) => {
+            ctx.error(&format!("compgen: {e}"));
+            return Ok(2);
+        }
+    };
+
+    let word = parsed.rest.first().map(String::as_str).unwrap_or("");
+    let matches = parsed.spec.generate(word);
+    for m in &matches {
+        writeln!(ctx.out, "{m}")?;
+    }
+
+    Ok(if matches.is_empty() { 1 } else { 0 })
+}
+
+pub fn complete(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> io::Result<i32> {
+    let parsed = match completion::parse_spec_args(args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            ctx.error(&format!("complete: {e}"));
+            return Ok(2);
+        }
+    };
+
+    if parsed.remove {
+        if parsed.rest.is_empty() {
+            shell.completions.clear();
+        }
+        for name in &parsed.rest {
+            shell.completions.remove(name);
+        }
+        return Ok(0);
+    }
+
+    let spec = parsed.spec;
+    let has_spec = !spec.actions.is_empty() || spec.wordlist.is_some() || spec.function.is_some();
+
+    if parsed.print || !has_spec {
+        if parsed.rest.is_empty() {
+            for (name, spec) in &shell.completions {
+                writeln!(ctx.out, "{}", spec.to_command(name))?;
+            }
+            return Ok(0);
+        }
+
+        let mut status = 0;
+        for name in &parsed.rest {
+            match shell.completions.get(name) {
+                Some(spec) => writeln!(ctx.out, "{}", spec.to_command(name))?,
+                None => {
+                    ctx.error(&format!("complete: {name}: no completion specification"));
+                    status = 1;
+                }
+            }
+        }
+        return Ok(status);
+    }
+
+    for name in parsed.rest {
+        shell.completions.insert(name, spec.clone());
+    }
+    Ok(0)
+}
+
+// dotenv [--unset] [file ...]: export (or remove) the variables assigned in
+// each file, `.env` by default.
+pub fn dotenv(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> i32 {
+    let (unset, files) = match args {
+        ["--unset", rest @ ..] => (true, rest),
+        _ => (false, args),
+    };
+    let files = if files.is_empty() {
+        &[".env"][..]
+    } else {
+        files
+    };
+
+    let mut status = 0;
+    for file in files {
+        let contents = match fs::read_to_string(file) {
+            Ok(contents) => contents,
+            Err(e) => {
+                ctx.error(&format!("dotenv: {file}: {}", error_text(&e)));
+                status = 1;
+                continue;
+            }
+        };
+
+        let parsed = env_file::parse(&contents);
+        for line in parsed.skipped {
+            ctx.error(&format!(
+                "dotenv: {file}:{line}: not an assignment, skipped"
+            ));
+        }
+        for (key, value) in parsed.vars {
+            if unset {
+                shell.vars.unset(&key);
+            } else {
+                shell.vars.export(&key, Some(&value));
+            }
+        }
+    }
+    status
+}
+
+// export [-n] [-f] [name[=value] ...]: exports variables to the commands
+// the shell runs (-n: stops exporting them); with no names, lists them.
+// With -f, the names are functions, which other oxide shells it starts
+// define for themselves.
+pub fn export(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> io::Result<i32> {
+    let (unexport, names) = match args {
+        ["-n", "-f", rest @ ..] | ["-f", "-n", rest @ ..] => {
+            return export_functions(shell, rest, true, ctx);
+        }
+        ["-f", rest @ ..] => return export_functions(shell, rest, false, ctx),
+        ["-n", rest @ ..] => (true, rest),
+        ["-p", rest @ ..] => (false, rest),
+        _ => (false, args),
+    };
+    if names.is_empty() {
+        for (name, value) in shell.vars.exported() {
+            writeln!(ctx.out, "export {name}={}", vars::quote(&value))?;
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for arg in names {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (*arg, None),
+        };
+        if !env_file::is_name(name) {
+            ctx.error(&format!("export: `{arg}': not a valid identifier"));
+            status = 1;
+            continue;
+        }
+        if unexport {
+            shell.vars.unexport(name);
+        } else {
+            shell.vars.export(name, value);
+        }
+    }
+    Ok(status)
+}
+
+fn export_functions(
+    shell: &mut Shell,
+    names: &[&str],
+    unexport: bool,
+    ctx: &mut ExecutionContext,
+) -> io::Result<i32> {
+    if names.is_empty() {
+        for name in function_env::exported() {
+            writeln!(ctx.out, "export -f {name}")?;
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for name in names {
+        match shell.functions.get(*name) {
+            Some(_) if unexport => function_env::unexport(name),
+            Some(body) => function_env::export(name, body),
+            None => {
+                ctx.error(&format!("export: {name}: not a function"));
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+// local [name[=value] ...]: declares variables that belong to the function
+// that's running, hiding any of the same name until it returns. With no
+// names, lists the ones it has declared.
+pub fn local(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> io::Result<i32> {
+    if shell.function_depth == 0 {
+        ctx.error("local: can only be used in a function");
+        return Ok(1);
+    }
+    if args.is_empty() {
+        for (name, value) in shell.vars.locals() {
+            writeln!(ctx.out, "{name}={}", vars::quote(&value))?;
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (*arg, None),
+        };
+        if !env_file::is_name(name) {
+            ctx.error(&format!("local: `{arg}': not a valid identifier"));
+            status = 1;
+            continue;
+        }
+        shell.vars.declare_local(name, value);
+    }
+    Ok(status)
+}
+
+// alias [-p] [name[=value] ...]: defines aliases; a name on its own, or
+// no names at all, prints them in a form that defines them again.
+pub fn alias(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> io::Result<i32> {
+    let names = match args {
+        ["-p", rest @ ..] => rest,
+        _ => args,
+    };
+    if names.is_empty() {
+        for (name, value) in &shell.aliases {
+            print_alias(&mut ctx.out, name, value)?;
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for arg in names {
+        match arg.split_once('=') {
+            Some((name, _)) if name.is_empty() || name.contains(['/', '$', '`', '\\']) => {
+                ctx.error(&format!("alias: `{name}': invalid alias name"));
+                status = 1;
+            }
+            Some((name, value)) => {
+                shell.aliases.insert(name.to_string(), value.to_string());
+            }
+            None => match shell.aliases.get(*arg) {
+                Some(value) => print_alias(&mut ctx.out, arg, value)?,
+                None => {
+                    ctx.error(&format!("alias: {arg}: not found"));
+                    status = 1;
+                }
+            },
+        }
+    }
+    Ok(status)
+}
+
+fn print_alias(out: &mut dyn Write, name: &str, value: &str) -> io::Result<()> {
+    let quoted = value.replace('\'', "'\\''");
+    writeln!(out, "alias {name}='{quoted}'")
+}
+
+// let expr ...: evaluates each arithmetic expression. The status is 0 if
+// the last one's value isn't 0, 1 if it is.
+pub fn let_(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> i32 {
+    if args.is_empty() {
+        ctx.error("let: expression expected");
+        return 1;
+    }
+    evaluate(shell, "let", args, ctx)
+}
+
+// (( expr )): the same as `let "expr"`.
+pub fn arith(shell: &mut Shell, expr: &str, ctx: &mut ExecutionContext) -> i32 {
+    evaluate(shell, "((", &[expr], ctx)
+}
+
+fn evaluate(shell: &mut Shell, name: &str, exprs: &[&str], ctx: &mut ExecutionContext) -> i32 {
+    let mut value = 0;
+    for expr in exprs {
+        match arith::eval(expr, &mut shell.vars) {
+            Ok(v) => value = v,
+            Err(e) => {
+                ctx.error(&format!("{name}: {e}"));
+                return 1;
+            }
+        }
+    }
+    if value != 0 { 0 } else { 1 }
+}
+
+// unalias [-a] name ...: removes aliases (-a: all of them).
+pub fn unalias(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> i32 {
+    let names = match args {
+        ["-a", ..] => {
+            shell.aliases.clear();
+            return 0;
+        }
+        ["--", rest @ ..] => rest,
+        [flag, ..] if flag.starts_with('-') => {
+            ctx.error(&format!("unalias: {flag}: invalid option"));
+            return 2;
+        }
+        [] => {
+            ctx.error("unalias: usage: unalias [-a] name [name ...]");
+            return 2;
+        }
+        _ => args,
+    };
+
+    let mut status = 0;
+    for name in names {
+        if shell.aliases.remove(*name).is_none() {
+            ctx.error(&format!("unalias: {name}: not found"));
+            status = 1;
+        }
+    }
+    status
+}
+
+// unset [-v] name ...: removes variables, exported or not; unset -f
+// removes functions.
+pub fn unset(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> i32 {
+    let names = match args {
+        ["-f", rest @ ..] => {
+            for name in rest {
+                shell.functions.remove(*name);
+                function_env::unexport(name);
+            }
+            return 0;
+        }
+        ["-v" | "--", rest @ ..] => rest,
+        [flag, ..] if flag.starts_with('-') => {
+            ctx.error(&format!("unset: {flag}: invalid option"));
+            return 2;
+        }
+        _ => args,
+    };
+
+    let mut status = 0;
+    for name in names {
+        if !env_file::is_name(name) {
+            ctx.error(&format!("unset: `{name}': not a valid identifier"));
+            status = 1;
+            continue;
+        }
+        shell.vars.unset(name);
+    }
+    status
+}
+
+// set -U [name [value ...]] sets (or lists) universal variables and
+// set -eU name erases one. set -o/+o name (or -e/-u/-x/-C and their +
+// forms) toggles shell options; a bare set -o lists them, and a bare set
+// lists every variable. Any other arguments, or all of those after `--`,
+// become the positional parameters.
+pub fn set(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> io::Result<i32> {
+    if args.is_empty() {
+        for (name, value) in shell.vars.all() {
+            writeln!(ctx.out, "{name}={}", vars::quote(&value))?;
+        }
+        return Ok(0);
+    }
+
+    let mut universal = false;
+    // -e erases with -U, and is errexit otherwise
+    let mut e_flag = None;
+    // `--` ends the options, even with nothing after it
+    let mut operands = false;
+    let mut rest = args;
+
+    while let [flag, tail @ ..] = rest {
+        if *flag == "--" {
+            rest = tail;
+            operands = true;
+            break;
+        }
+        let (on, flags) = match (flag.strip_prefix('-'), flag.strip_prefix('+')) {
+            (Some(flags), _) => (true, flags),
+            (_, Some(flags)) => (false, flags),
+            _ => break,
+        };
+        rest = tail;
+        // a lone `-` or `+` ends them too
+        if flags.is_empty() {
+            break;
+        }
+
+        for c in flags.chars() {
+            match c {
+                'U' if on => universal = true,
+                'e' => e_flag = Some(on),
+                'o' => {
+                    let [name, tail @ ..] = rest else {
+                        for name in ShellOptions::NAMES {
+                            let value = shell.options.get_mut(name).is_some_and(|v| *v);
+                            writeln!(ctx.out, "{name:<15} {}", if value { "on" } else { "off" })?;
+                        }
+                        return Ok(0);
+                    };
+                    let Some(option) = shell.options.get_mut(name) else {
+                        ctx.error(&format!("set: {name}: invalid option name"));
+                        return Ok(2);
+                    };
+                    *option = on;
+                    rest = tail;
+                }
+                _ => {
+                    let Some(option) = shell.options.flag_mut(c) else {
+                        ctx.error(&format!(
+                            "set: {}{c}: invalid option",
+                            if on { '-' } else { '+' }
+                        ));
+                        return Ok(2);
+                    };
+                    *option = on;
+                }
+            }
+        }
+    }
+
+    let erase = universal && e_flag == Some(true);
+    if let Some(on) = e_flag
+        && !universal
+    {
+        shell.options.errexit = on;
+    }
+
+    if !universal {
+        if operands || !rest.is_empty() {
+            shell.positional = rest.iter().map(|arg| arg.to_string()).collect();
+        }
+        return Ok(0);
+    }
+
+    match rest {
+        [] if !erase => {
+            shell.universal.sync();
+            for (name, value) in shell.universal.vars() {
+                writeln!(ctx.out, "{name} {value}")?;
+            }
+            Ok(0)
+        }
+        [name] if erase => match shell.universal.erase(name) {
+            Ok(true) => Ok(0),
+            Ok(false) => Ok(1),
+            Err(e) => {
+                ctx.error(&format!("set: {}", error_text(&e)));
+                Ok(1)
+            }
+        },
+        [name, values @ ..] if !erase && env_file::is_name(name) => {
+            match shell.universal.set(name, &values.join(" ")) {
+                Ok(()) => Ok(0),
+                Err(e) => {
+                    ctx.error(&format!("set: {}", error_text(&e)));
+                    Ok(1)
+                }
+            }
+        }
+        [name, ..] if !erase => {
+            ctx.error(&format!("set: {name}: invalid variable name"));
+            Ok(2)
+        }
+        _ => {
+            ctx.error("set: usage: set -U [name [value ...]] | set -eU name | set [-+]o option");
+            Ok(2)
+        }
+    }
+}
+
+pub fn wait(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> i32 {
+    let mut any = false;
+    let mut deadline = None;
+    let mut targets = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match *arg {
+            "-n" => any = true,
+            "-t" => {
+                let Some(secs) = iter
+                    .next()
+                    .and_then(|s| s.parse::<f64>().ok())
+                    .filter(|s| s.is_finite() && *s >= 0.0)
+                else {
+                    ctx.error("wait: -t: invalid timeout");
+                    return 2;
+                };
+                deadline = Some(Instant::now() + Duration::from_secs_f64(secs));
+            }
+            spec if spec.starts_with('%') => match shell.jobs.resolve(spec) {
+                Ok(id) => targets.push(id),
+                Err(e) => {
+                    ctx.error(&format!("wait: {e}"));
+                    return 127;
+                }
+            },
+            pid => match pid.parse().ok().and_then(|p| shell.jobs.find_pid(p)) {
+                Some(id) => targets.push(id),
+                None => {
+                    ctx.error(&format!("wait: pid {pid} is not a child of this shell"));
+                    return 127;
+                }
+            },
+        }
+    }
+
+    let explicit = !targets.is_empty();
+    if !explicit {
+        targets = shell.jobs.iter().map(|j| j.id).collect();
+    }
+    if targets.is_empty() {
+        return if any { 127 } else { 0 };
+    }
+
+    let is_done = |shell: &Shell, id: usize| shell.jobs.get(id).is_none_or(|j| j.is_done());
+
+    loop {
+        if any {
+            if let Some(id) = targets.iter().copied().find(|&id| is_done(shell, id)) {
+                return finish_job(shell, id);
+            }
+        } else if targets.iter().all(|&id| is_done(shell, id)) {
+            let mut status = 0;
+            for id in targets {
+                status = finish_job(shell, id);
+            }
+            return if explicit { status } else { 0 };
+        }
+
+        match deadline {
+            None => {
+                if !shell.jobs.reap(true) {
+                    // a trapped signal ends the wait, for its trap to run
+                    return signals::pending().map_or(127, |signal| 128 + signal);
+                }
+            }
+            Some(deadline) => {
+                if let Some(signal) = signals::pending() {
+                    return 128 + signal;
+                }
+                if !shell.jobs.reap(false) {
+                    if Instant::now() >= deadline {
+                        return WAIT_TIMEOUT;
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+        }
+    }
+}
+
+// jobs [-l|-p] [jobspec ...]: lists jobs with their state. Finished jobs
+// are shown one last time and then forgotten.
+pub fn jobs(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> io::Result<i32> {
+    let mut long = false;
+    let mut pids_only = false;
+    let mut targets = Vec::new();
+
+    for arg in args {
+        match *arg {
+            "-l" => long = true,
+            "-p" => pids_only = true,
+            spec => match shell.jobs.resolve(spec) {
+                Ok(id) => targets.push(id),
+                Err(e) => {
+                    ctx.error(&format!("jobs: {e}"));
+                    return Ok(1);
+                }
+            },
+        }
+    }
+
+    while shell.jobs.reap(false) {}
+    if targets.is_empty() {
+        targets = shell.jobs.iter().map(|j| j.id).collect();
+    }
+
+    for id in targets {
+        let Some(job) = shell.jobs.get(id) else {
+            continue;
+        };
+        let pids = job.processes.iter().map(|p| p.pid.to_string());
+
+        if pids_only {
+            for pid in pids {
+                writeln!(ctx.out, "{pid}")?;
+            }
+            continue;
+        }
+
+        let state = job.state();
+        let suffix = if job.is_done() || job.is_stopped() {
+            ""
+        } else {
+            " &"
+        };
+        let pid = if long {
+            format!("{} ", pids.collect::<Vec<_>>().join(" "))
+        } else {
+            String::new()
+        };
+        writeln!(
+            ctx.out,
+            "[{id}]{}  {pid}{state:<24}{}{suffix}",
+            shell.jobs.marker(id),
+            job.command
+        )?;
+
+        if job.is_done() {
+            shell.jobs.remove(id);
+        }
+    }
+    Ok(0)
+}
+
+// fg [jobspec]: continues a job if it was stopped and waits for it as if
+// it had been started in the foreground.
+pub fn fg(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> io::Result<i32> {
+    let id = match job_arg("fg", shell, args, ctx) {
+        Ok(id) => id,
+        Err(status) => return Ok(status),
+    };
+    let Some(job) = shell.jobs.get(id) else {
+        return Ok(1);
+    };
+    writeln!(ctx.out, "{}", job.command)?;
+    // before the job takes over the terminal
+    ctx.out.flush()?;
+    if shell.job_control {
+        terminal::give_terminal(job.pgid);
+    }
+    continue_job(shell, id);
+    Ok(wait_foreground(shell, id).unwrap_or(128 + libc::SIGTSTP))
+}
+
+// bg [jobspec]: continues a stopped job in the background.
+pub fn bg(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> io::Result<i32> {
+    let id = match job_arg("bg", shell, args, ctx) {
+        Ok(id) => id,
+        Err(status) => return Ok(status),
+    };
+    continue_job(shell, id);
+    let Some(job) = shell.jobs.get(id) else {
+        return Ok(1);
+    };
+    writeln!(ctx.out, "[{id}]{} {} &", shell.jobs.marker(id), job.command)?;
+    Ok(0)
+}
+
+// exit [n]: exits the shell with status n, or that of the last command.
+pub fn exit(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> i32 {
+    let status = match args {
+        [] => shell.status,
+        [n] => match n.parse::<i32>() {
+            Ok(n) => n & 0xff,
+            Err(_) => {
+                ctx.error(&format!("exit: {n}: numeric argument required"));
+                2
+            }
+        },
+        _ => {
+            ctx.error("exit: too many arguments");
+            return 1;
+        }
+    };
+    shell.exit_status = Some(status);
+    status
+}
+
+// break [n]: ends the loop that's running, or with n, that many loops
+// out from it.
+pub fn break_(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> i32 {
+    loop_control(shell, "break", args, LoopControl::Break, ctx)
+}
+
+// continue [n]: skips ahead to the next round of the loop that's running,
+// or of the nth one out from it.
+pub fn continue_(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> i32 {
+    loop_control(shell, "continue", args, LoopControl::Continue, ctx)
+}
+
+fn loop_control(
+    shell: &mut Shell,
+    name: &str,
+    args: &[&str],
+    control: fn(usize) -> LoopControl,
+    ctx: &mut ExecutionContext,
+) -> i32 {
+    let levels = match args {
+        [] => 1,
+        [n] => match n.parse::<i64>() {
+            Ok(levels) if levels > 0 => levels as usize,
+            Ok(_) => {
+                ctx.error(&format!("{name}: {n}: loop count out of range"));
+                return 1;
+            }
+            Err(_) => {
+                ctx.error(&format!("{name}: {n}: numeric argument required"));
+                return 1;
+            }
+        },
+        _ => {
+            ctx.error(&format!("{name}: too many arguments"));
+            return 1;
+        }
+    };
+    if shell.loop_depth == 0 {
+        ctx.error(&format!(
+            "{name}: only meaningful in a `while' or `until' loop"
+        ));
+        return 0;
+    }
+    // more levels than there are loops just ends them all
+    shell.loop_control = Some(control(levels.min(shell.loop_depth)));
+    0
+}
+
+// return [n]: ends the function that's running, with a status of n, or of
+// the last command if there isn't one.
+pub fn return_(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> i32 {
+    if shell.function_depth == 0 {
+        ctx.error("return: can only `return' from a function");
+        return 1;
+    }
+    let status = match args {
+        [] => shell.status,
+        [n] => match n.parse::<i32>() {
+            Ok(n) => n & 0xff,
+            Err(_) => {
+                ctx.error(&format!("return: {n}: numeric argument required"));
+                2
+            }
+        },
+        _ => {
+            ctx.error("return: too many arguments");
+            return 1;
+        }
+    };
+    shell.returning = true;
+    status
+}
+
+// shift [n]: drops the first n positional parameters, 1 by default, so
+// $2 becomes $1. Fails, leaving them as they are, if there aren't n.
+pub fn shift(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> i32 {
+    let count = match args {
+        [] => 1,
+        [n] => match n.parse::<i64>() {
+            Ok(count) if count >= 0 => count as usize,
+            Ok(_) => {
+                ctx.error(&format!("shift: {n}: shift count out of range"));
+                return 1;
+            }
+            Err(_) => {
+                ctx.error(&format!("shift: {n}: numeric argument required"));
+                return 1;
+            }
+        },
+        _ => {
+            ctx.error("shift: too many arguments");
+            return 1;
+        }
+    };
+    if count > shell.positional.len() {
+        return 1;
+    }
+    shell.positional.drain(..count);
+    0
+}
+
+// trap [-lp] [[action] signal ...]: with an action, runs it when one of the
+// signals arrives (EXIT: when the shell exits); '' ignores the signals and
+// `-` puts them back. Without one, lists the traps that are set.
+pub fn trap(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> io::Result<i32> {
+    let (action, specs) = match args {
+        ["-l"] => {
+            for &(name, signal) in &traps::SIGNALS[1..] {
+                writeln!(ctx.out, "{signal:>2}) SIG{name}")?;
+            }
+            return Ok(0);
+        }
+        [] => {
+            for (signal, action) in shell.traps.iter() {
+                print_trap(&mut ctx.out, signal, action)?;
+            }
+            return Ok(0);
+        }
+        ["-p", specs @ ..] => (None, specs),
+        // `trap INT` alone resets it, like `trap - INT`
+        [spec] => (Some("-"), std::slice::from_ref(spec)),
+        ["--", action, specs @ ..] | [action, specs @ ..] => (Some(*action), specs),
+    };
+
+    let mut status = 0;
+    for spec in specs {
+        let Some(signal) = traps::signal_number(spec) else {
+            ctx.error(&format!("trap: {spec}: invalid signal specification"));
+            status = 1;
+            continue;
+        };
+        match action {
+            None => {
+                if let Some(action) = shell.traps.get(signal) {
+                    print_trap(&mut ctx.out, signal, action)?;
+                }
+            }
+            Some("-") => shell.traps.reset(signal),
+            Some(action) => shell.traps.set(signal, action),
+        }
+    }
+    if action.is_none() && specs.is_empty() {
+        for (signal, action) in shell.traps.iter() {
+            print_trap(&mut ctx.out, signal, action)?;
+        }
+    }
+    Ok(status)
+}
+
+// In a form that can be run again to set the same trap.
+fn print_trap(out: &mut dyn Write, signal: i32, action: &str) -> io::Result<()> {
+    let quoted = action.replace('\'', "'\\''");
+    writeln!(out, "trap -- '{quoted}' {}", traps::signal_name(signal))
+}
+
+// disown [-a] [jobspec ...]: forgets jobs (the current one by default, all
+// of them with -a), so they no longer show up in `jobs` or count as the
+// shell's jobs for fg, bg or wait. The processes themselves keep running.
+pub fn disown(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> i32 {
+    let ids: Vec<usize> = match args {
+        ["-a"] => shell.jobs.iter().map(|j| j.id).collect(),
+        [] => match shell.jobs.current() {
+            Some(id) => vec![id],
+            None => {
+                ctx.error("disown: current: no such job");
+                return 1;
+            }
+        },
+        specs => {
+            let mut ids = Vec::new();
+            for spec in specs {
+                match shell.jobs.resolve(spec) {
+                    Ok(id) => ids.push(id),
+                    Err(e) => {
+                        ctx.error(&format!("disown: {e}"));
+                        return 1;
+                    }
+                }
+            }
+            ids
+        }
+    };
+
+    for id in ids {
+        shell.jobs.remove(id);
+    }
+    0
+}
+
+// Signals `kill` can send besides the ones `trap` knows.
+const KILL_SIGNALS: &[(&str, i32)] = &[
+    ("KILL", libc::SIGKILL),
+    ("PIPE", libc::SIGPIPE),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+];
+
+// kill [-s signal | -signal] pid|jobspec ...: sends a signal, TERM by
+// default, to processes or to every process of a job. A process group is
+// `-pgid` after `--`. kill -l lists the signals.
+pub fn kill(shell: &mut Shell, args: &[&str], ctx: &mut ExecutionContext) -> io::Result<i32> {
+    let (spec, targets) = match args {
+        ["-l"] => {
+            let mut signals: Vec<(&str, i32)> = traps::SIGNALS[1..]
+                .iter()
+                .chain(KILL_SIGNALS)
+                .copied()
+                .collect();
+            signals.sort_by_key(|&(_, signal)| signal);
+            for (name, signal) in signals {
+                writeln!(ctx.out, "{signal:>2}) SIG{name}")?;
+            }
+            return Ok(0);
+        }
+        ["-s", spec, targets @ ..] => (Some(*spec), targets),
+        ["-s"] => (None, &[][..]),
+        ["--", targets @ ..] => (None, targets),
+        [flag, targets @ ..] if flag.len() > 1 && flag.starts_with('-') => {
+            (Some(&flag[1..]), targets)
+        }
+        targets => (None, targets),
+    };
+    let signal = match spec {
+        None => libc::SIGTERM,
+        Some(spec) => match kill_signal(spec) {
+            Some(signal) => signal,
+            None => {
+                ctx.error(&format!("kill: {spec}: invalid signal specification"));
+                return Ok(1);
+            }
+        },
+    };
+    let targets = targets.strip_prefix(&["--"]).unwrap_or(targets);
+    if targets.is_empty() {
+        ctx.error("kill: usage: kill [-s signal | -signal] pid | jobspec ... or kill -l");
+        return Ok(2);
+    }
+
+    let mut status = 0;
+    for target in targets {
+        let sent = if target.starts_with('%') {
+            match shell.jobs.resolve(target) {
+                Ok(id) => signal_job(shell, id, signal),
+                Err(e) => {
+                    ctx.error(&format!("kill: {e}"));
+                    status = 1;
+                    continue;
+                }
+            }
+        } else if is_pid(target) {
+            let pid = target.parse().unwrap_or(0);
+            unsafe { libc::kill(pid, signal) == 0 }
+        } else {
+            ctx.error(&format!(
+                "kill: {target}: arguments must be process or job IDs"
+            ));
+            status = 1;
+            continue;
+        };
+        if !sent {
+            let e = io::Error::last_os_error();
+            ctx.error(&format!("kill: ({target}) - {}", error_text(&e)));
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+fn kill_signal(spec: &str) -> Option<i32> {
+    traps::signal_number(spec).or_else(|| {
+        if let Ok(n) = spec.parse::<i32>() {
+            return KILL_SIGNALS.iter().any(|&(_, sig)| sig == n).then_some(n);
+        }
+        let upper = spec.to_ascii_uppercase();
+        let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+        KILL_SIGNALS
+            .iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(_, sig)| sig)
+    })
+}
+
+// A pid, or with a `-` in front, a process group.
+fn is_pid(arg: &str) -> bool {
+    let digits = arg.strip_prefix('-').unwrap_or(arg);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+// Sends `signal` to the processes of a job still running: to its process
+// group under job control, otherwise to each one.
+fn signal_job(shell: &mut Shell, id: usize, signal: i32) -> bool {
+    let Some(job) = shell.jobs.get(id) else {
+        return false;
+    };
+    let sent = if shell.job_control {
+        unsafe { libc::kill(-job.pgid, signal) == 0 }
+    } else {
+        job.processes
+            .iter()
+            .filter(|p| p.status.is_none())
+            .all(|p| unsafe { libc::kill(p.pid, signal) } == 0)
+    };
+    if sent && signal == libc::SIGCONT {
+        shell.jobs.set_stopped(id, false);
+    }
+    sent
+}
+
+// The job named by fg/bg's optional argument (the current job without
+// one). `fg 2` means the same as `fg %2`.
+fn job_arg(
+    name: &str,
+    shell: &Shell,
+    args: &[&str],
+    ctx: &mut ExecutionContext,
+) -> Result<usize, i32> {
+    let spec = match args {
+        [] => "%+".to_string(),
+        [spec] if spec.starts_with('%') => spec.to_string(),
+        [spec] => format!("%{spec}"),
+        _ => {
+            ctx.error(&format!("{name}: too many arguments"));
+            return Err(2);
+        }
+    };
+    shell.jobs.resolve(&spec).map_err(|e| {
+        if args.is_empty() {
+            ctx.error(&format!("{name}: current: no such job"));
+        } else {
+            ctx.error(&format!("{name}: {e}"));
+        }
+        1
+    })
+}
+
+fn continue_job(shell: &mut Shell, id: usize) {
+    let Some(job) = shell.jobs.get(id) else {
+        return;
+    };
+    if shell.job_control {
+        unsafe { libc::kill(-job.pgid, libc::SIGCONT) };
+    } else {
+        for process in job.processes.iter().filter(|p| p.status.is_none()) {
+            unsafe { libc::kill(process.pid, libc::SIGCONT) };
+        }
+    }
+    shell.jobs.set_stopped(id, false);
+}
+
+fn finish_job(shell: &mut Shell, id: usize) -> i32 {
+    shell
+        .jobs
+        .remove(id)
+        .and_then(|job| job.status())
+        .unwrap_or(127)
 }
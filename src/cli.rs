@@ -0,0 +1,80 @@
+pub struct Options {
+    pub login: bool,
+    pub interactive: bool,
+    pub norc: bool,
+    // -c: commands to run instead of reading them
+    pub command: Option<String>,
+    pub args: Vec<String>,
+}
+
+pub enum Invocation {
+    Run(Options),
+    Trust { dir: Option<String>, trusted: bool },
+    Version,
+    Help,
+}
+
+pub const USAGE: &str = "\
+Usage: oxide [option] ... [script [arg] ...]
+       oxide [option] ... -c command [name [arg] ...]
+       oxide --trust|--untrust [dir]
+
+Options:
+  -c command          run command, with name as $0 and the args after it
+  -l, --login         act as a login shell, running the login profile first
+  -i, --interactive   force the shell to run interactively
+      --norc          do not read the startup file
+  -V, --version       print version information and exit
+  -h, --help          print this help and exit
+      --trust [dir]   let dir's .envrc (the current directory's by default)
+                      be loaded as it is now
+      --untrust [dir] stop loading dir's .envrc";
+
+pub fn version() -> String {
+    format!("oxide {}", env!("CARGO_PKG_VERSION"))
+}
+
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Invocation, String> {
+    let mut options = Options {
+        login: false,
+        interactive: false,
+        norc: false,
+        command: None,
+        args: Vec::new(),
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-l" | "--login" => options.login = true,
+            "-i" | "--interactive" => options.interactive = true,
+            "--norc" => options.norc = true,
+            "-c" => match args.next() {
+                Some(command) => options.command = Some(command),
+                None => return Err("-c: option requires an argument".to_string()),
+            },
+            "-V" | "--version" => return Ok(Invocation::Version),
+            "-h" | "--help" => return Ok(Invocation::Help),
+            "--trust" | "--untrust" => {
+                return Ok(Invocation::Trust {
+                    dir: args.next(),
+                    trusted: arg == "--trust",
+                });
+            }
+            "--" => {
+                options.args.extend(args);
+                break;
+            }
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("{flag}: invalid option"));
+            }
+            _ => {
+                options.args.push(arg);
+                options.args.extend(args);
+                break;
+            }
+        }
+    }
+
+    Ok(Invocation::Run(options))
+}
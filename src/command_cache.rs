@@ -0,0 +1,38 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+// Remembers where commands were found on PATH so each launch costs a single
+// stat instead of a walk over every PATH entry. Cleared whenever PATH changes.
+#[derive(Default)]
+pub struct CommandCache {
+    path: Option<OsString>,
+    entries: HashMap<String, PathBuf>,
+}
+
+impl CommandCache {
+    pub fn resolve(&mut self, name: &str) -> Option<PathBuf> {
+        if name.contains('/') {
+            let path = Path::new(name);
+            return path.is_file().then(|| path.to_path_buf());
+        }
+
+        let current = env::var_os("PATH");
+        if current != self.path {
+            self.entries.clear();
+            self.path = current;
+        }
+
+        if let Some(path) = self.entries.get(name) {
+            if path.is_file() {
+                return Some(path.clone());
+            }
+            self.entries.remove(name);
+        }
+
+        let path = pathsearch::find_executable_in_path(name)?;
+        self.entries.insert(name.to_string(), path.clone());
+        Some(path)
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use crate::built_in_commands::BUILTINS;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    Builtin,
+    Command,
+    Directory,
+    Export,
+    File,
+    Hostname,
+    User,
+    Variable,
+}
+
+impl Action {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "builtin" => Some(Action::Builtin),
+            "command" => Some(Action::Command),
+            "directory" => Some(Action::Directory),
+            "export" => Some(Action::Export),
+            "file" => Some(Action::File),
+            "hostname" => Some(Action::Hostname),
+            "user" => Some(Action::User),
+            "variable" => Some(Action::Variable),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Action::Builtin => "builtin",
+            Action::Command => "command",
+            Action::Directory => "directory",
+            Action::Export => "export",
+            Action::File => "file",
+            Action::Hostname => "hostname",
+            Action::User => "user",
+            Action::Variable => "variable",
+        }
+    }
+}
+
+// How candidates are compared against the word being completed, set with
+// OXIDE_COMPLETION_CASE. Smart case (the default) ignores case unless the
+// word itself contains an uppercase letter.
+#[derive(Clone, Copy, PartialEq)]
+pub enum CaseMode {
+    Sensitive,
+    Insensitive,
+    Smart,
+}
+
+impl CaseMode {
+    pub fn from_env() -> Self {
+        match env::var("OXIDE_COMPLETION_CASE").as_deref() {
+            Ok("sensitive") => CaseMode::Sensitive,
+            Ok("insensitive") => CaseMode::Insensitive,
+            _ => CaseMode::Smart,
+        }
+    }
+}
+
+pub struct Matcher<'a> {
+    word: &'a str,
+    folded: Option<String>,
+}
+
+impl<'a> Matcher<'a> {
+    pub fn new(word: &'a str, case: CaseMode) -> Self {
+        let ignore_case = match case {
+            CaseMode::Sensitive => false,
+            CaseMode::Insensitive => true,
+            CaseMode::Smart => !word.chars().any(char::is_uppercase),
+        };
+        Matcher {
+            word,
+            folded: ignore_case.then(|| word.to_lowercase()),
+        }
+    }
+
+    pub fn matches(&self, candidate: &str) -> bool {
+        match &self.folded {
+            Some(folded) => candidate.to_lowercase().starts_with(folded.as_str()),
+            None => candidate.starts_with(self.word),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct CompSpec {
+    pub actions: Vec<Action>,
+    pub wordlist: Option<String>,
+    pub function: Option<String>,
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+}
+
+// Options shared by `complete` and `compgen`, plus the flags only
+// `complete` understands.
+#[derive(Default)]
+pub struct SpecArgs {
+    pub spec: CompSpec,
+    pub print: bool,
+    pub remove: bool,
+    pub rest: Vec<String>,
+}
+
+pub fn parse_spec_args(args: &[&str]) -> Result<SpecArgs, String> {
+    let mut parsed = SpecArgs::default();
+    let mut iter = args.iter();
+
+    while let Some(&arg) = iter.next() {
+        if arg == "--" {
+            parsed.rest.extend(iter.map(|s| s.to_string()));
+            break;
+        }
+        let Some(flags) = arg.strip_prefix('-').filter(|f| !f.is_empty()) else {
+            parsed.rest.push(arg.to_string());
+            parsed.rest.extend(iter.map(|s| s.to_string()));
+            break;
+        };
+
+        for flag in flags.chars() {
+            let spec = &mut parsed.spec;
+            match flag {
+                'b' => spec.actions.push(Action::Builtin),
+                'c' => spec.actions.push(Action::Command),
+                'd' => spec.actions.push(Action::Directory),
+                'e' => spec.actions.push(Action::Export),
+                'f' => spec.actions.push(Action::File),
+                'u' => spec.actions.push(Action::User),
+                'v' => spec.actions.push(Action::Variable),
+                'p' => parsed.print = true,
+                'r' => parsed.remove = true,
+                'A' | 'W' | 'F' | 'P' | 'S' => {
+                    let Some(&value) = iter.next() else {
+                        return Err(format!("-{flag}: option requires an argument"));
+                    };
+                    match flag {
+                        'A' => spec.actions.push(
+                            Action::from_name(value)
+                                .ok_or_else(|| format!("{value}: invalid action name"))?,
+                        ),
+                        'W' => spec.wordlist = Some(value.to_string()),
+                        'F' => spec.function = Some(value.to_string()),
+                        'P' => spec.prefix = Some(value.to_string()),
+                        _ => spec.suffix = Some(value.to_string()),
+                    }
+                }
+                _ => return Err(format!("-{flag}: invalid option")),
+            }
+        }
+    }
+
+    Ok(parsed)
+}
+
+impl CompSpec {
+    pub fn generate(&self, word: &str) -> Vec<String> {
+        let case = CaseMode::from_env();
+        let matcher = Matcher::new(word, case);
+        let mut matches = BTreeSet::new();
+
+        for action in &self.actions {
+            matches.extend(complete_action(*action, word, case));
+        }
+        if let Some(words) = &self.wordlist {
+            matches.extend(
+                words
+                    .split_whitespace()
+                    .filter(|w| matcher.matches(w))
+                    .map(String::from),
+            );
+        }
+
+        let prefix = self.prefix.as_deref().unwrap_or("");
+        let suffix = self.suffix.as_deref().unwrap_or("");
+        matches
+            .into_iter()
+            .map(|m| format!("{prefix}{m}{suffix}"))
+            .collect()
+    }
+
+    // Renders the spec back into the `complete` invocation that creates it.
+    pub fn to_command(&self, name: &str) -> String {
+        let mut parts = vec!["complete".to_string()];
+        for action in &self.actions {
+            parts.push(format!("-A {}", action.name()));
+        }
+        if let Some(words) = &self.wordlist {
+            parts.push(format!("-W '{words}'"));
+        }
+        if let Some(function) = &self.function {
+            parts.push(format!("-F {function}"));
+        }
+        if let Some(prefix) = &self.prefix {
+            parts.push(format!("-P '{prefix}'"));
+        }
+        if let Some(suffix) = &self.suffix {
+            parts.push(format!("-S '{suffix}'"));
+        }
+        parts.push(name.to_string());
+        parts.join(" ")
+    }
+}
+
+fn complete_action(action: Action, word: &str, case: CaseMode) -> Vec<String> {
+    let matcher = Matcher::new(word, case);
+    match action {
+        Action::Builtin => BUILTINS
+            .iter()
+            .filter(|b| matcher.matches(b))
+            .map(|b| b.to_string())
+            .collect(),
+        Action::Command => {
+            if word.contains('/') {
+                return complete_files(word, case, |meta| {
+                    meta.is_dir() || meta.permissions().mode() & 0o111 != 0
+                });
+            }
+            let mut commands = complete_action(Action::Builtin, word, case);
+            commands.extend(path_executables(&matcher));
+            commands
+        }
+        Action::Directory => complete_files(word, case, |meta| meta.is_dir()),
+        Action::File => complete_files(word, case, |_| true),
+        Action::Export | Action::Variable => env::vars()
+            .map(|(name, _)| name)
+            .filter(|name| matcher.matches(name))
+            .collect(),
+        Action::Hostname => hostnames()
+            .into_iter()
+            .filter(|h| matcher.matches(h))
+            .collect(),
+        Action::User => fs::read_to_string("/etc/passwd")
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.split(':').next())
+            .filter(|user| !user.is_empty() && matcher.matches(user))
+            .map(String::from)
+            .collect(),
+    }
+}
+
+fn complete_files(word: &str, case: CaseMode, keep: impl Fn(&fs::Metadata) -> bool) -> Vec<String> {
+    let (dir, base) = match word.rfind('/') {
+        Some(pos) => (&word[..=pos], &word[pos + 1..]),
+        None => ("", word),
+    };
+
+    let dirs = if dir.is_empty() || Path::new(dir).is_dir() {
+        vec![dir.to_string()]
+    } else {
+        expand_abbreviated_dir(dir, case)
+    };
+    let matcher = Matcher::new(base, case);
+
+    let mut found = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { &dir }) else {
+            continue;
+        };
+        found.extend(entries.flatten().filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !matcher.matches(&name) || (name.starts_with('.') && !base.starts_with('.')) {
+                return None;
+            }
+            let meta = fs::metadata(entry.path()).ok()?;
+            keep(&meta).then(|| format!("{dir}{name}"))
+        }));
+    }
+    found
+}
+
+// Expands every segment of an abbreviated directory as a prefix, zsh style,
+// so "/u/lo/" yields "/usr/local/" (and any other directories that fit).
+fn expand_abbreviated_dir(dir: &str, case: CaseMode) -> Vec<String> {
+    let mut expanded = vec![if dir.starts_with('/') {
+        "/".to_string()
+    } else {
+        String::new()
+    }];
+
+    for segment in dir.split('/').filter(|s| !s.is_empty()) {
+        let matcher = Matcher::new(segment, case);
+        let mut next = Vec::new();
+
+        for parent in &expanded {
+            let exact = format!("{parent}{segment}");
+            if segment == "." || segment == ".." || Path::new(&exact).is_dir() {
+                next.push(format!("{exact}/"));
+                continue;
+            }
+
+            let Ok(entries) = fs::read_dir(if parent.is_empty() { "." } else { parent }) else {
+                continue;
+            };
+            next.extend(entries.flatten().filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let is_dir = entry.path().is_dir();
+                (is_dir && matcher.matches(&name) && !name.starts_with('.'))
+                    .then(|| format!("{parent}{name}/"))
+            }));
+        }
+
+        expanded = next;
+    }
+
+    expanded
+}
+
+fn path_executables(matcher: &Matcher) -> Vec<String> {
+    let Some(path) = env::var_os("PATH") else {
+        return Vec::new();
+    };
+
+    env::split_paths(&path)
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.flatten())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !matcher.matches(&name) {
+                return None;
+            }
+            let meta = fs::metadata(entry.path()).ok()?;
+            (meta.is_file() && meta.permissions().mode() & 0o111 != 0).then_some(name)
+        })
+        .collect()
+}
+
+fn hostnames() -> Vec<String> {
+    let hosts = env::var("HOSTFILE").unwrap_or_else(|_| "/etc/hosts".to_string());
+    let mut names: Vec<String> = fs::read_to_string(hosts)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .flat_map(|line| line.split_whitespace().skip(1))
+        .map(String::from)
+        .collect();
+    names.extend(ssh_hosts());
+    names
+}
+
+fn ssh_hosts() -> Vec<String> {
+    let mut hosts = Vec::new();
+    let home = env::var("HOME").unwrap_or_default();
+
+    if !home.is_empty() {
+        let config = fs::read_to_string(format!("{home}/.ssh/config")).unwrap_or_default();
+        for line in config.lines() {
+            let line = line.trim();
+            let Some((keyword, rest)) = line.split_once(|c: char| c.is_whitespace() || c == '=')
+            else {
+                continue;
+            };
+            if !keyword.eq_ignore_ascii_case("host") {
+                continue;
+            }
+            hosts.extend(
+                rest.split(|c: char| c.is_whitespace() || c == '=')
+                    .filter(|h| !h.is_empty() && !h.contains(['*', '?', '!']))
+                    .map(String::from),
+            );
+        }
+    }
+
+    let known_hosts = [
+        format!("{home}/.ssh/known_hosts"),
+        "/etc/ssh/ssh_known_hosts".to_string(),
+    ];
+    for file in known_hosts {
+        let contents = fs::read_to_string(file).unwrap_or_default();
+        for line in contents.lines() {
+            let mut fields = line.split_whitespace();
+            let Some(mut first) = fields.next() else {
+                continue;
+            };
+            if first.starts_with('@') {
+                let Some(next) = fields.next() else {
+                    continue;
+                };
+                first = next;
+            }
+            // hashed entries (HashKnownHosts) can't be turned back into names
+            if first.starts_with('#') || first.starts_with('|') {
+                continue;
+            }
+            hosts.extend(first.split(',').filter_map(|host| {
+                let host = match host.strip_prefix('[') {
+                    Some(bracketed) => bracketed.split(']').next()?,
+                    None => host,
+                };
+                (!host.is_empty() && !host.contains(['*', '?', '!'])).then(|| host.to_string())
+            }));
+        }
+    }
+
+    hosts
+}
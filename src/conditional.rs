@@ -0,0 +1,314 @@
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+use crate::{ParseError, Shell, Token, arith, expand, glob};
+
+// `[[ expression ]]`: tests on strings, numbers and files, joined with
+// `&&`, `||`, `!` and parentheses. Its words are expanded but not split or
+// globbed; the right side of `==` and `!=` is a pattern, and that of `=~`
+// an extended regular expression, which leaves what it matched in
+// BASH_REMATCH and each group's match in BASH_REMATCH_1, BASH_REMATCH_2...
+pub enum Expr<'a> {
+    Not(Box<Expr<'a>>),
+    And(Box<Expr<'a>>, Box<Expr<'a>>),
+    Or(Box<Expr<'a>>, Box<Expr<'a>>),
+    // `-f file`
+    Unary(&'a str, &'a str),
+    // `a == b`, `a < b`, `a -lt b`
+    Binary(&'a str, &'a str, &'a str),
+    // `a =~ regex`, where the regex can run over several tokens, as the
+    // `(`, `|` and `)` in `^(a|b)$` are tokens of their own
+    Regex(&'a str, &'a [Token]),
+    // a word alone: whether it's non-empty
+    Word(&'a str),
+}
+
+const UNARY: &[&str] = &[
+    "-a", "-b", "-c", "-d", "-e", "-f", "-h", "-L", "-n", "-p", "-r", "-s", "-S", "-w", "-x", "-z",
+];
+
+const BINARY: &[&str] = &[
+    "==", "=", "!=", "<", ">", "=~", "-eq", "-ne", "-lt", "-le", "-gt", "-ge",
+];
+
+// Parses a whole `[[ ... ]]`, brackets included.
+pub fn parse(tokens: &[Token]) -> Result<Expr<'_>, ParseError<'_>> {
+    let close = &tokens[tokens.len() - 1];
+    let mut parser = Parser {
+        tokens: &tokens[1..tokens.len() - 1],
+        pos: 0,
+        close,
+    };
+    let expr = parser.or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(ParseError::Unexpected(token)),
+    }
+}
+
+// Runs a `[[ ... ]]` parse has already checked: 0 if it's true, 1 if it
+// isn't, 2 if it couldn't be worked out.
+pub fn run(shell: &mut Shell, tokens: &[Token]) -> i32 {
+    let Ok(expr) = parse(tokens) else {
+        unreachable!("checked by parse_group");
+    };
+    match eval(shell, &expr) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("oxide: {e}");
+            2
+        }
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    // the `]]`, for errors about running out of tokens
+    close: &'a Token,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<&'a Token, ParseError<'a>> {
+        let token = self.peek().ok_or(ParseError::Unexpected(self.close))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> Result<Expr<'a>, ParseError<'a>> {
+        let mut left = self.and()?;
+        while self.peek().is_some_and(|t| t.is_op("||")) {
+            self.pos += 1;
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr<'a>, ParseError<'a>> {
+        let mut left = self.not()?;
+        while self.peek().is_some_and(|t| t.is_op("&&")) {
+            self.pos += 1;
+            left = Expr::And(Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr<'a>, ParseError<'a>> {
+        if matches!(self.peek(), Some(Token::Word(w)) if w == "!") {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr<'a>, ParseError<'a>> {
+        let token = self.next()?;
+        if token.is_op("(") {
+            let expr = self.or()?;
+            let close = self.next()?;
+            if !close.is_op(")") {
+                return Err(ParseError::Unexpected(close));
+            }
+            return Ok(expr);
+        }
+        let Token::Word(word) = token else {
+            return Err(ParseError::Unexpected(token));
+        };
+
+        let op = match self.peek() {
+            Some(Token::Word(op)) if BINARY.contains(&op.as_str()) => Some(op.as_str()),
+            Some(Token::Op(op)) if op == "<" || op == ">" => Some(op.as_str()),
+            _ => None,
+        };
+        if let Some(op) = op {
+            self.pos += 1;
+            if op == "=~" {
+                return Ok(Expr::Regex(word, self.regex()?));
+            }
+            return match self.next()? {
+                Token::Word(right) => Ok(Expr::Binary(word, op, right)),
+                other => Err(ParseError::Unexpected(other)),
+            };
+        }
+        if UNARY.contains(&word.as_str())
+            && let Some(Token::Word(operand)) = self.peek()
+        {
+            self.pos += 1;
+            return Ok(Expr::Unary(word, operand));
+        }
+        Ok(Expr::Word(word))
+    }
+
+    // The tokens of a regex, up to a `&&` or `||`, or a `)` it didn't open.
+    fn regex(&mut self) -> Result<&'a [Token], ParseError<'a>> {
+        let start = self.pos;
+        let mut depth = 0;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Op(op) if op == "&&" || op == "||" => break,
+                Token::Op(op) if op == ")" && depth == 0 => break,
+                Token::Op(op) if op == ")" => depth -= 1,
+                Token::Op(op) if op == "(" => depth += 1,
+                Token::ProcessSub(..) => return Err(ParseError::Unexpected(token)),
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(ParseError::at(self.peek().or(Some(self.close))));
+        }
+        Ok(&self.tokens[start..self.pos])
+    }
+}
+
+fn eval(shell: &mut Shell, expr: &Expr) -> Result<bool, String> {
+    Ok(match expr {
+        Expr::Not(expr) => !eval(shell, expr)?,
+        Expr::And(left, right) => eval(shell, left)? && eval(shell, right)?,
+        Expr::Or(left, right) => eval(shell, left)? || eval(shell, right)?,
+        Expr::Word(word) => !operand(shell, word)?.is_empty(),
+        Expr::Unary(op, word) => unary(op, &operand(shell, word)?),
+        Expr::Binary(left, op, right) => {
+            let left = operand(shell, left)?;
+            match *op {
+                "==" | "=" | "!=" => {
+                    let pattern = expand::word(shell, right)?;
+                    glob::matches_pattern(&pattern, &left, &mut shell.patterns) == (*op != "!=")
+                }
+                "<" => left < operand(shell, right)?,
+                ">" => left > operand(shell, right)?,
+                _ => {
+                    let left = arith::eval(&left, &mut shell.vars)?;
+                    let right = arith::eval(&operand(shell, right)?, &mut shell.vars)?;
+                    match *op {
+                        "-eq" => left == right,
+                        "-ne" => left != right,
+                        "-lt" => left < right,
+                        "-le" => left <= right,
+                        "-gt" => left > right,
+                        _ => left >= right,
+                    }
+                }
+            }
+        }
+        Expr::Regex(left, tokens) => {
+            let text = operand(shell, left)?;
+            let mut pattern = String::new();
+            for token in *tokens {
+                match token {
+                    Token::Word(word) => pattern.push_str(&operand(shell, word)?),
+                    other => pattern.push_str(&other.to_string()),
+                }
+            }
+            let groups = regex(&pattern, &text)?;
+            remember_match(shell, groups.as_deref().unwrap_or_default());
+            groups.is_some()
+        }
+    })
+}
+
+fn operand(shell: &mut Shell, word: &str) -> Result<String, String> {
+    Ok(glob::literal(&expand::word(shell, word)?))
+}
+
+fn unary(op: &str, operand: &str) -> bool {
+    match op {
+        "-n" => !operand.is_empty(),
+        "-z" => operand.is_empty(),
+        "-h" | "-L" => fs::symlink_metadata(operand).is_ok_and(|m| m.file_type().is_symlink()),
+        "-r" => access(operand, libc::R_OK),
+        "-w" => access(operand, libc::W_OK),
+        "-x" => access(operand, libc::X_OK),
+        _ => {
+            let Ok(meta) = fs::metadata(operand) else {
+                return false;
+            };
+            let kind = meta.file_type();
+            match op {
+                "-b" => kind.is_block_device(),
+                "-c" => kind.is_char_device(),
+                "-d" => kind.is_dir(),
+                "-f" => kind.is_file(),
+                "-p" => kind.is_fifo(),
+                "-S" => kind.is_socket(),
+                "-s" => meta.size() > 0,
+                // -a and -e
+                _ => true,
+            }
+        }
+    }
+}
+
+fn access(path: &str, mode: libc::c_int) -> bool {
+    let Ok(path) = CString::new(path) else {
+        return false;
+    };
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
+}
+
+// Matches `text` against an extended regular expression, returning what
+// it matched and what each group did, "" for one that took no part.
+fn regex(pattern: &str, text: &str) -> Result<Option<Vec<String>>, String> {
+    let invalid = || format!("{pattern}: invalid regular expression");
+    let c_pattern = CString::new(pattern).map_err(|_| invalid())?;
+    let c_text = CString::new(text).map_err(|_| invalid())?;
+
+    unsafe {
+        let mut re: libc::regex_t = std::mem::zeroed();
+        if libc::regcomp(&mut re, c_pattern.as_ptr(), libc::REG_EXTENDED) != 0 {
+            return Err(invalid());
+        }
+        // room for a group at every `(`, as the count regcomp keeps isn't
+        // reachable through libc
+        let unset = libc::regmatch_t {
+            rm_so: -1,
+            rm_eo: -1,
+        };
+        let mut groups = vec![unset; pattern.matches('(').count() + 1];
+        let found = libc::regexec(&re, c_text.as_ptr(), groups.len(), groups.as_mut_ptr(), 0) == 0;
+        libc::regfree(&mut re);
+        if !found {
+            return Ok(None);
+        }
+        // those past the last that matched may not be groups at all
+        while groups.last().is_some_and(|m| m.rm_so < 0) {
+            groups.pop();
+        }
+        let groups = groups
+            .iter()
+            .map(
+                |m| match (usize::try_from(m.rm_so), usize::try_from(m.rm_eo)) {
+                    (Ok(start), Ok(end)) => text[start..end].to_string(),
+                    _ => String::new(),
+                },
+            )
+            .collect();
+        Ok(Some(groups))
+    }
+}
+
+// Replaces BASH_REMATCH and its numbered groups with those of the last
+// match, or nothing if it didn't match.
+fn remember_match(shell: &mut Shell, groups: &[String]) {
+    let stale: Vec<String> = shell
+        .vars
+        .all()
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| name.starts_with("BASH_REMATCH_"))
+        .collect();
+    for name in stale {
+        shell.vars.unset(&name);
+    }
+    let whole = groups.first().map_or("", String::as_str);
+    shell.vars.set("BASH_REMATCH", whole);
+    for (i, group) in groups.iter().enumerate().skip(1) {
+        shell.vars.set(&format!("BASH_REMATCH_{i}"), group);
+    }
+}
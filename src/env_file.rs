@@ -0,0 +1,69 @@
+// Parser for KEY=VALUE environment files (.env, .envrc). Only assignments
+// are understood; nothing in the file is ever executed.
+//
+//   # comment
+//   export NAME=value   # trailing comment
+//   QUOTED="a \"b\" $c"
+//   LITERAL='no escapes here'
+pub struct EnvFile {
+    pub vars: Vec<(String, String)>,
+    // line numbers (1-based) that weren't assignments
+    pub skipped: Vec<usize>,
+}
+
+pub fn parse(contents: &str) -> EnvFile {
+    let mut vars = Vec::new();
+    let mut skipped = Vec::new();
+
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+
+        match line.split_once('=') {
+            Some((key, value)) if is_name(key) => vars.push((key.to_string(), parse_value(value))),
+            _ => skipped.push(n + 1),
+        }
+    }
+
+    EnvFile { vars, skipped }
+}
+
+pub fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_value(raw: &str) -> String {
+    let raw = raw.trim_start();
+    let mut chars = raw.chars();
+
+    match chars.next() {
+        Some('\'') => chars.take_while(|&c| c != '\'').collect(),
+        Some('"') => {
+            let mut value = String::new();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => match chars.next() {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some(other) => value.push(other),
+                        None => value.push('\\'),
+                    },
+                    _ => value.push(c),
+                }
+            }
+            value
+        }
+        _ => {
+            let end = raw.find(" #").unwrap_or(raw.len());
+            raw[..end].trim_end().to_string()
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use std::ffi::{CStr, CString};
+use std::io::Read;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::arith;
+use crate::env_file::is_name;
+use crate::glob;
+use crate::{
+    Shell, Streams, Token, error_text, nesting, pipe, read_subcommand, spawn, subshell, tokenize,
+};
+
+// Stands in for a `$` the tokenizer found unquoted or inside double
+// quotes, which starts an expansion once the command is about to run; a
+// quoted `$` stays a `$`. No argument can contain NUL, so it's free to use.
+pub const MARKER: char = '\0';
+
+// After MARKER, makes the `*`, `?` or `[` that follows a pattern
+// character for globbing rather than itself, as it is when unquoted.
+pub const GLOB: char = '\u{1}';
+
+// After MARKER, marks the expansion that follows as being inside double
+// quotes, so its result isn't split into fields or globbed.
+pub const QUOTED: char = '\u{2}';
+
+// Expands the words of a command that's about to run: each can become
+// several fields, and any field that's a pattern becomes the files it
+// matches. Assignments and redirection targets stay one word. Words
+// inside a group are left for when the group's own commands run.
+pub fn words(shell: &mut Shell, tokens: Vec<Token>) -> Result<Vec<Token>, String> {
+    let mut out = Vec::with_capacity(tokens.len());
+    let mut depth = 0;
+    // before the command name, where `NAME=value` is an assignment
+    let mut command_start = true;
+    // after `export` or `local`, whose `NAME=value` arguments are
+    // assignments too
+    let mut declaration = false;
+    for (i, token) in tokens.iter().enumerate() {
+        let change = nesting(&tokens, i);
+        depth += change;
+        let Token::Word(w) = token.clone() else {
+            if matches!(token, Token::Op(op) if op == "|") {
+                command_start = true;
+                declaration = false;
+            }
+            out.push(token.clone());
+            continue;
+        };
+        if depth > 0 || change < 0 {
+            out.push(Token::Word(w));
+            continue;
+        }
+
+        let target = matches!(out.last(), Some(Token::Op(op)) if op.contains(['<', '>']));
+        let assignment = w.split_once('=').is_some_and(|(name, _)| is_name(name))
+            && (command_start || declaration);
+        if !target && !assignment {
+            command_start = false;
+            declaration |= w == "export" || w == "local";
+        }
+        if !w.contains(MARKER) {
+            out.push(Token::Word(w));
+        } else if target || assignment {
+            out.push(Token::Word(glob::literal(&word(shell, &w)?)));
+        } else {
+            for field in fields(shell, &w, true)? {
+                match glob::expand(&field, shell.options.globstar, &mut shell.patterns) {
+                    Some(paths) if !paths.is_empty() || shell.options.nullglob => {
+                        out.extend(paths.into_iter().map(Token::Word));
+                    }
+                    _ => out.push(Token::Word(glob::literal(&field))),
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+// Expands a word that stays one word, whatever its expansions hold.
+pub fn word(shell: &mut Shell, text: &str) -> Result<String, String> {
+    Ok(fields(shell, text, false)?.join(" "))
+}
+
+// The fields a word becomes as it's expanded. The results of expansions
+// outside double quotes are split on the characters in $IFS if `split`
+// is set, and any `*`, `?` or `[` in them are pattern characters; the
+// rest of the word is kept as it is.
+struct Fields {
+    // None when nothing is split
+    ifs: Option<String>,
+    fields: Vec<String>,
+    current: String,
+    // whether `current` is a field even while it's empty, which it is
+    // once anything but an unquoted expansion has been added to it
+    started: bool,
+    // whether the last split was on IFS whitespace, which a following
+    // IFS character that isn't whitespace belongs with
+    after_space: bool,
+}
+
+impl Fields {
+    fn push(&mut self, text: &str) {
+        self.current.push_str(text);
+        self.started = true;
+        self.after_space = false;
+    }
+
+    fn expansion(&mut self, value: &str, quoted: bool) {
+        let Some(ifs) = self.ifs.as_deref().filter(|_| !quoted) else {
+            self.push(value);
+            return;
+        };
+        for c in value.chars() {
+            if ifs.contains(c) {
+                let space = matches!(c, ' ' | '\t' | '\n');
+                if (space && self.started) || !(space || self.after_space) {
+                    self.fields.push(std::mem::take(&mut self.current));
+                    self.started = false;
+                }
+                self.after_space = space;
+                continue;
+            }
+            if matches!(c, '*' | '?' | '[') {
+                self.current.push(MARKER);
+                self.current.push(GLOB);
+            }
+            self.current.push(c);
+            self.started = true;
+            self.after_space = false;
+        }
+    }
+
+    // `$@`: each value is a field of its own, the first joined to what
+    // came before it and the last to what comes after. Where nothing is
+    // split, they're joined with spaces.
+    fn separate(&mut self, values: &[String], quoted: bool) {
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                if self.ifs.is_none() {
+                    self.current.push(' ');
+                } else if self.started {
+                    self.fields.push(std::mem::take(&mut self.current));
+                    self.started = false;
+                    self.after_space = true;
+                }
+            }
+            self.expansion(value, quoted);
+        }
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        if self.started {
+            self.fields.push(self.current);
+        }
+        self.fields
+    }
+}
+
+// Expands `$?`, `$1`, `${10}`, `$@`, `$*`, `$#`, `$$`, `$!`, `$0`, `$-`,
+// `$NAME`, `${NAME}`, `$(command)`, `$((expr))` and `~`. A variable that isn't set expands to
+// nothing, or is an error under `set -u`; a `$` that doesn't start any of
+// these stays as it is.
+fn fields(shell: &mut Shell, text: &str, split: bool) -> Result<Vec<String>, String> {
+    let ifs = split.then(|| shell.vars.get("IFS").unwrap_or_else(|| " \t\n".to_string()));
+    let mut out = Fields {
+        ifs,
+        fields: Vec::new(),
+        current: String::with_capacity(text.len()),
+        started: false,
+        after_space: false,
+    };
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != MARKER {
+            out.push(c.encode_utf8(&mut [0; 4]));
+            continue;
+        }
+        let quoted = chars.next_if_eq(&QUOTED).is_some();
+        match chars.peek() {
+            Some('?') => {
+                chars.next();
+                out.expansion(&shell.status.to_string(), quoted);
+            }
+            // kept for globbing once the word is expanded
+            Some(&GLOB) => out.push(&MARKER.to_string()),
+            Some('~') => {
+                chars.next();
+                let mut prefix = String::new();
+                while let Some(c) = chars.next_if(|&c| !matches!(c, '/' | ':' | MARKER)) {
+                    prefix.push(c);
+                }
+                match tilde(shell, &prefix) {
+                    Some(dir) => out.push(&dir),
+                    None => out.push(&format!("~{prefix}")),
+                }
+            }
+            Some('(') => {
+                chars.next();
+                let command = read_subcommand(&mut chars);
+                // $((expr)) is arithmetic, which can itself hold expansions
+                let value = match command.strip_prefix('(').and_then(|c| c.strip_suffix(')')) {
+                    Some(expr) => {
+                        let expr = word(shell, &expr.replace('$', &MARKER.to_string()))?;
+                        arith::eval(&expr, &mut shell.vars)?.to_string()
+                    }
+                    None => substitute(shell, &command)?,
+                };
+                out.expansion(&value, quoted);
+            }
+            Some('{') => {
+                chars.next();
+                let mut name = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    name.push(c);
+                }
+                let special = matches!(name.as_str(), "@" | "*" | "#" | "$" | "!" | "-");
+                let number = !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit());
+                if closed && (is_name(&name) || special || number) {
+                    parameter(shell, &name, quoted, &mut out)?;
+                } else {
+                    // `${` with no closing brace, or something inside it
+                    // that isn't a name, is left alone
+                    out.push(&format!("${{{name}{}", if closed { "}" } else { "" }));
+                }
+            }
+            Some(&c) if is_name_start(c) => {
+                let name = read_name(&mut chars);
+                out.expansion(&variable(shell, &name)?, quoted);
+            }
+            // `$1` is only ever one digit; `$12` is `$1` and a 2
+            Some(&c @ ('0'..='9' | '@' | '*' | '#' | '$' | '!' | '-')) => {
+                chars.next();
+                parameter(shell, c.encode_utf8(&mut [0; 4]), quoted, &mut out)?;
+            }
+            // not an expansion after all
+            _ => out.push("$"),
+        }
+    }
+    Ok(out.finish())
+}
+
+// Runs a `$(command)` in a subshell and returns its output, less any
+// trailing newlines.
+fn substitute(shell: &mut Shell, command: &str) -> Result<String, String> {
+    let tokens = tokenize(command);
+    let (mut reader, writer) = pipe().ok_or("cannot create pipe")?;
+    let streams = Streams::from([(1, writer.into())]);
+    let pid = subshell(shell, &tokens, &[], streams, None).map_err(|e| error_text(&e))?;
+    let mut output = Vec::new();
+    let _ = reader.read_to_end(&mut output);
+    shell.substitution_status = Some(spawn::wait(pid).unwrap_or(127));
+
+    // no argument can hold a NUL, so any in the output are dropped
+    let mut output = String::from_utf8_lossy(&output).replace('\0', "");
+    output.truncate(output.trim_end_matches('\n').len());
+    Ok(output)
+}
+
+// `~` is $HOME, `~+` $PWD, `~-` $OLDPWD and `~user` that user's home
+// directory. Anything else, or one that isn't set, stays as it is.
+fn tilde(shell: &Shell, prefix: &str) -> Option<String> {
+    match prefix {
+        "" => shell.vars.get("HOME"),
+        "+" => shell.vars.get("PWD"),
+        "-" => shell.vars.get("OLDPWD"),
+        user => {
+            let name = CString::new(user).ok()?;
+            let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+            if entry.is_null() {
+                return None;
+            }
+            let dir = unsafe { CStr::from_ptr((*entry).pw_dir) };
+            Some(dir.to_string_lossy().into_owned())
+        }
+    }
+}
+
+// Adds the value of `$name` to `out`. `$@` and an unquoted `$*` are each
+// positional parameter as a field of its own; a quoted `"$*"` is one field
+// with them joined by the first character of $IFS.
+fn parameter(shell: &Shell, name: &str, quoted: bool, out: &mut Fields) -> Result<(), String> {
+    match name {
+        "@" => out.separate(&shell.positional, quoted),
+        "*" if !quoted => out.separate(&shell.positional, quoted),
+        "*" => {
+            let separator = match shell.vars.get("IFS") {
+                Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+                None => " ".to_string(),
+            };
+            out.expansion(&shell.positional.join(&separator), quoted);
+        }
+        "#" => out.expansion(&shell.positional.len().to_string(), quoted),
+        _ => out.expansion(&variable(shell, name)?, quoted),
+    }
+    Ok(())
+}
+
+fn variable(shell: &Shell, name: &str) -> Result<String, String> {
+    let value = match name {
+        "0" => Some(shell.name.clone()),
+        "$" => Some(shell.pid.to_string()),
+        "!" => shell.last_background.map(|pid| pid.to_string()),
+        "-" => {
+            let mut flags = shell.options.flags();
+            if shell.job_control {
+                flags.push('m');
+            }
+            if shell.interactive {
+                flags.push('i');
+            }
+            Some(flags)
+        }
+        "PIPESTATUS" => {
+            let statuses: Vec<String> = shell.pipestatus.iter().map(i32::to_string).collect();
+            Some(statuses.join(" "))
+        }
+        _ if name.bytes().all(|b| b.is_ascii_digit()) => name
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| shell.positional.get(n.checked_sub(1)?).cloned()),
+        _ => shell.vars.get(name),
+    };
+    match value {
+        Some(value) => Ok(value),
+        None if shell.options.nounset => Err(format!("{name}: unbound variable")),
+        None => Ok(String::new()),
+    }
+}
+
+fn read_name(chars: &mut Peekable<Chars>) -> String {
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|&c| c.is_ascii_alphanumeric() || c == '_') {
+        name.push(c);
+    }
+    name
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
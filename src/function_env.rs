@@ -0,0 +1,226 @@
+// Functions passed on to child shells with `export -f`. Like bash, each is
+// an environment variable, OXIDE_FUNC_name%%, holding `() ` and its body as
+// text: the body's tokens on the first line, written so the tokenizer
+// reads the same ones back, then the bodies of any here-documents in it.
+
+use std::collections::BTreeMap;
+use std::env;
+
+use crate::expand::{GLOB, MARKER, QUOTED};
+use crate::{Script, Stage, Token, incomplete, nesting, parse_group, read_subcommand};
+
+const PREFIX: &str = "OXIDE_FUNC_";
+const SUFFIX: &str = "%%";
+
+fn var_name(name: &str) -> String {
+    format!("{PREFIX}{name}{SUFFIX}")
+}
+
+pub fn is_exported(name: &str) -> bool {
+    env::var_os(var_name(name)).is_some()
+}
+
+pub fn export(name: &str, body: &[Token]) {
+    // SAFETY: the shell is single-threaded
+    unsafe { env::set_var(var_name(name), encode(body)) };
+}
+
+pub fn unexport(name: &str) {
+    // SAFETY: the shell is single-threaded
+    unsafe { env::remove_var(var_name(name)) };
+}
+
+// The names of the functions exported so far, as the environment has them.
+pub fn exported() -> Vec<String> {
+    env::vars_os()
+        .filter_map(|(key, _)| {
+            let key = key.into_string().ok()?;
+            Some(key.strip_prefix(PREFIX)?.strip_suffix(SUFFIX)?.to_string())
+        })
+        .collect()
+}
+
+// The functions a parent shell exported. A variable that doesn't hold
+// exactly one function body is left alone, so nothing in the environment
+// can make the shell run a command as it starts.
+pub fn import() -> BTreeMap<String, Vec<Token>> {
+    env::vars()
+        .filter_map(|(key, value)| {
+            let name = key.strip_prefix(PREFIX)?.strip_suffix(SUFFIX)?;
+            Some((name.to_string(), decode(value.strip_prefix("() ")?)?))
+        })
+        .collect()
+}
+
+fn encode(body: &[Token]) -> String {
+    let mut line = Vec::with_capacity(body.len());
+    let mut heredocs = String::new();
+    let mut tokens = body.iter();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Op(op) if op == "<<" => {
+                let Some(Token::Word(text)) = tokens.next() else {
+                    continue;
+                };
+                line.push(heredoc(text, &mut heredocs));
+            }
+            Token::Op(op) => line.push(op.clone()),
+            Token::ProcessSub(c, command) => line.push(format!("{c}({command})")),
+            Token::Word(word) => line.push(quote(word)),
+        }
+    }
+    format!("() {}\n{heredocs}", line.join(" "))
+}
+
+fn decode(value: &str) -> Option<Vec<Token>> {
+    let mut lines = value.split_inclusive('\n');
+    // the first line, unless a quoted newline carries it on
+    let mut input = String::new();
+    for line in lines.by_ref() {
+        input.push_str(line);
+        if !incomplete(&input) {
+            break;
+        }
+    }
+    let input = input.strip_suffix('\n').unwrap_or(&input);
+    let script = Script::parse(input, || lines.next().map(str::to_string)).ok()?;
+    let body = script.tokens;
+    // a `{ }` or `( )` group, with nothing after it but its redirections
+    if body.is_empty() || nesting(&body, 0) <= 0 {
+        return None;
+    }
+    match parse_group(&body) {
+        Ok((Stage::Group(_) | Stage::Subshell(_), _)) => {}
+        _ => return None,
+    }
+    Some(body)
+}
+
+// A word written so that it tokenizes back to itself: the text around its
+// expansions and pattern characters in single quotes, each expansion as
+// it was found, in double quotes if it was in them.
+fn quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:+@%^".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        return word.to_string();
+    }
+
+    let mut out = String::with_capacity(word.len() + 2);
+    let mut literal = String::new();
+    let mut chars = word.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != MARKER {
+            literal.push(c);
+            continue;
+        }
+        if !literal.is_empty() {
+            out.push_str(&single_quote(&std::mem::take(&mut literal)));
+        }
+        match chars.peek() {
+            Some(&GLOB) => {
+                chars.next();
+                out.extend(chars.next());
+            }
+            Some('~') => {
+                chars.next();
+                out.push('~');
+            }
+            Some(&QUOTED) => {
+                chars.next();
+                out.push_str("\"$");
+                expansion(&mut chars, &mut out);
+                out.push('"');
+            }
+            _ => {
+                out.push('$');
+                expansion(&mut chars, &mut out);
+            }
+        }
+    }
+    if !literal.is_empty() || out.is_empty() {
+        out.push_str(&single_quote(&literal));
+    }
+    out
+}
+
+fn single_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+// Copies what follows a `$`, as far as expand would take it to be part of
+// the expansion.
+fn expansion(chars: &mut std::iter::Peekable<std::str::Chars>, out: &mut String) {
+    match chars.peek() {
+        Some('(') => {
+            chars.next();
+            out.push('(');
+            out.push_str(&read_subcommand(chars));
+            out.push(')');
+        }
+        Some('{') => {
+            for c in chars.by_ref() {
+                out.push(c);
+                if c == '}' {
+                    break;
+                }
+            }
+        }
+        Some(&c) if c.is_ascii_alphabetic() || c == '_' => {
+            while let Some(c) = chars.next_if(|&c| c.is_ascii_alphanumeric() || c == '_') {
+                out.push(c);
+            }
+        }
+        Some('0'..='9' | '?' | '@' | '*' | '#' | '$' | '!' | '-') => out.extend(chars.next()),
+        _ => {}
+    }
+}
+
+// The operator for a here-document, with its body added to `heredocs`
+// under a delimiter it doesn't contain. One that had expansions marked in
+// it gets an unquoted delimiter and its `$`, `` ` `` and `\` escaped.
+fn heredoc(text: &str, heredocs: &mut String) -> String {
+    let mut delimiter = "OXIDE_EOF".to_string();
+    let mut n = 0;
+    while text.lines().any(|line| line == delimiter) {
+        n += 1;
+        delimiter = format!("OXIDE_EOF{n}");
+    }
+
+    if text.contains(MARKER) {
+        heredocs.push_str(&unmark(text));
+        heredocs.push_str(&delimiter);
+        heredocs.push('\n');
+        format!("<<{delimiter}")
+    } else {
+        heredocs.push_str(text);
+        heredocs.push_str(&delimiter);
+        heredocs.push('\n');
+        format!("<<'{delimiter}'")
+    }
+}
+
+fn unmark(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            MARKER => {
+                chars.next_if_eq(&QUOTED);
+                out.push('$');
+                if chars.next_if_eq(&'(').is_some() {
+                    out.push('(');
+                    out.push_str(&read_subcommand(&mut chars));
+                    out.push(')');
+                } else if chars.next_if_eq(&'$').is_some() {
+                    out.push('$');
+                }
+            }
+            '$' | '`' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,373 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+
+use crate::expand::{GLOB, MARKER};
+
+// Filename generation. A word's pattern characters are the `*`, `?` and
+// `[` the tokenizer found unquoted, each marked with MARKER and GLOB;
+// any other character only matches itself.
+enum Piece {
+    Char(char),
+    // `*`
+    Any,
+    // `?`
+    One,
+    // `[abc]`, `[a-z]`, `[!abc]`
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Piece {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Piece::Char(p) => *p == c,
+            Piece::Any | Piece::One => true,
+            Piece::Class { negated, ranges } => {
+                ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+            }
+        }
+    }
+}
+
+// Compiled patterns, keyed by their text as the tokenizer marked it, so a
+// loop that matches the same pattern over and over compiles it once.
+#[derive(Default)]
+pub struct PatternCache {
+    entries: HashMap<String, Option<Vec<Piece>>>,
+}
+
+// Past this many patterns the cache starts over.
+const CACHE_SIZE: usize = 256;
+
+impl PatternCache {
+    // The pattern in `text`, or None if it has no pattern characters.
+    fn get(&mut self, text: &str) -> Option<&[Piece]> {
+        if !self.entries.contains_key(text) {
+            if self.entries.len() >= CACHE_SIZE {
+                self.entries.clear();
+            }
+            self.entries
+                .insert(text.to_string(), compile(&decode(text)));
+        }
+        self.entries[text].as_deref()
+    }
+}
+
+// The files and directories a word matches, sorted, or None if it has no
+// pattern characters. A `*` or `?` doesn't match a leading `.`, so hidden
+// files are only matched by a pattern that starts with one. With
+// `globstar`, a `**` component matches any number of directories.
+pub fn expand(word: &str, globstar: bool, cache: &mut PatternCache) -> Option<Vec<String>> {
+    let chars = decode(word);
+    if !chars.iter().any(|&(_, active)| active) {
+        return None;
+    }
+
+    let (mut paths, chars) = match chars.split_first() {
+        Some((('/', _), rest)) => (vec!["/".to_string()], rest),
+        _ => (vec![String::new()], chars.as_slice()),
+    };
+    let components: Vec<&[(char, bool)]> = chars.split(|&(c, _)| c == '/').collect();
+    for (i, component) in components.iter().enumerate() {
+        let last = i == components.len() - 1;
+        if globstar && matches!(component, [('*', true), ('*', true)]) {
+            let mut next = Vec::new();
+            for path in &paths {
+                if !last {
+                    next.push(path.clone());
+                }
+                let mut visited = HashSet::new();
+                if let Ok(meta) = fs::metadata(dir(path)) {
+                    visited.insert((meta.dev(), meta.ino()));
+                }
+                walk(path, last, &mut visited, &mut next);
+            }
+            paths = next;
+            continue;
+        }
+
+        let Some(pattern) = cache.get(&encode(component)) else {
+            let name: String = component.iter().map(|&(c, _)| c).collect();
+            paths = paths.iter().map(|path| join(path, &name)).collect();
+            continue;
+        };
+
+        let hidden = matches!(component.first(), Some(('.', _)));
+        let mut next = Vec::new();
+        for path in &paths {
+            let Ok(entries) = fs::read_dir(dir(path)) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let Ok(name) = entry.file_name().into_string() else {
+                    continue;
+                };
+                if name.starts_with('.') && !hidden {
+                    continue;
+                }
+                let name_chars: Vec<char> = name.chars().collect();
+                if !matches(pattern, &name_chars) {
+                    continue;
+                }
+                let matched = join(path, &name);
+                // only a directory can have more of the path below it
+                if last || fs::metadata(&matched).is_ok_and(|m| m.is_dir()) {
+                    next.push(matched);
+                }
+            }
+        }
+        paths = next;
+    }
+
+    // the literal parts after the last pattern weren't checked as they went
+    paths.retain(|path| fs::symlink_metadata(path).is_ok());
+    paths.sort();
+    Some(paths)
+}
+
+// Adds everything below `path` that isn't hidden to `out`: only the
+// directories, unless `files` is set. A directory reached again through a
+// symlink is listed but not descended into a second time.
+fn walk(path: &str, files: bool, visited: &mut HashSet<(u64, u64)>, out: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(dir(path)) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+        let child = join(path, &name);
+        match fs::metadata(&child) {
+            Ok(meta) if meta.is_dir() => {
+                out.push(child.clone());
+                if visited.insert((meta.dev(), meta.ino())) {
+                    walk(&child, files, visited, out);
+                }
+            }
+            _ if files => out.push(child),
+            _ => {}
+        }
+    }
+}
+
+// Paths are built up a component at a time, starting from "" for the
+// working directory or "/" for the root.
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() || path.ends_with('/') {
+        format!("{path}{name}")
+    } else {
+        format!("{path}/{name}")
+    }
+}
+
+fn dir(path: &str) -> &str {
+    if path.is_empty() { "." } else { path }
+}
+
+// Whether `text` matches the pattern in `word`, all of it at once: unlike
+// in a path, `*` and `?` match a `/` or a leading `.` too.
+pub fn matches_pattern(word: &str, text: &str, cache: &mut PatternCache) -> bool {
+    match cache.get(word) {
+        Some(pattern) => matches(pattern, &text.chars().collect::<Vec<_>>()),
+        None => literal(word) == text,
+    }
+}
+
+// The word with its pattern characters taken as themselves, for a pattern
+// that matched nothing.
+pub fn literal(word: &str) -> String {
+    decode(word).into_iter().map(|(c, _)| c).collect()
+}
+
+// Each character of a word, and whether it's a pattern character.
+fn decode(word: &str) -> Vec<(char, bool)> {
+    let mut chars = Vec::with_capacity(word.len());
+    let mut rest = word.chars().peekable();
+    while let Some(c) = rest.next() {
+        if c == MARKER && rest.next_if_eq(&GLOB).is_some() {
+            if let Some(c) = rest.next() {
+                chars.push((c, true));
+            }
+        } else {
+            chars.push((c, false));
+        }
+    }
+    chars
+}
+
+// The text `decode` would have read a path component from.
+fn encode(component: &[(char, bool)]) -> String {
+    let mut text = String::with_capacity(component.len());
+    for &(c, active) in component {
+        if active {
+            text.push(MARKER);
+            text.push(GLOB);
+        }
+        text.push(c);
+    }
+    text
+}
+
+// A path component's pattern, or None if it's just a name.
+fn compile(component: &[(char, bool)]) -> Option<Vec<Piece>> {
+    let mut pieces = Vec::new();
+    let mut pattern = false;
+    let mut i = 0;
+    while i < component.len() {
+        let (c, active) = component[i];
+        i += 1;
+        let piece = match (c, active) {
+            ('*', true) => Piece::Any,
+            ('?', true) => Piece::One,
+            ('[', true) => match class(&component[i..]) {
+                Some((piece, len)) => {
+                    i += len;
+                    piece
+                }
+                // with no closing `]` it's just a `[`
+                None => Piece::Char('['),
+            },
+            (c, _) => Piece::Char(c),
+        };
+        pattern |= !matches!(piece, Piece::Char(_));
+        pieces.push(piece);
+    }
+    pattern.then_some(pieces)
+}
+
+// Reads a bracket expression after its `[`, returning it and how many
+// characters it took up, `]` included. A `]` right at the start, or after
+// the `!` or `^` that negates it, is part of the set.
+fn class(chars: &[(char, bool)]) -> Option<(Piece, usize)> {
+    let mut i = 0;
+    let negated = matches!(chars.first(), Some(('!' | '^', _)));
+    if negated {
+        i += 1;
+    }
+    let start = i;
+    let mut ranges = Vec::new();
+    loop {
+        let &(c, _) = chars.get(i)?;
+        if c == ']' && i > start {
+            return Some((Piece::Class { negated, ranges }, i + 1));
+        }
+        match (chars.get(i + 1), chars.get(i + 2)) {
+            (Some(('-', _)), Some(&(hi, _))) if hi != ']' => {
+                ranges.push((c, hi));
+                i += 3;
+            }
+            _ => {
+                ranges.push((c, c));
+                i += 1;
+            }
+        }
+    }
+}
+
+// Matches a `*` by remembering where the last one was and, when what
+// follows it fails, letting it take one more character and trying again,
+// so matching takes time proportional to the pattern times the name
+// rather than growing exponentially with the number of `*`s.
+fn matches(pattern: &[Piece], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // the last `*` seen, and where in the name its match ends
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(Piece::Any) => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(piece) if piece.matches(name[n]) => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|piece| matches!(piece, Piece::Any))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The word with `*`, `?` and `[` marked as pattern characters, as the
+    // tokenizer would unquoted.
+    fn pattern(text: &str) -> String {
+        let mut word = String::new();
+        for c in text.chars() {
+            if matches!(c, '*' | '?' | '[') {
+                word.push(MARKER);
+                word.push(GLOB);
+            }
+            word.push(c);
+        }
+        word
+    }
+
+    fn matches_text(text: &str, candidate: &str) -> bool {
+        matches_pattern(&pattern(text), candidate, &mut PatternCache::default())
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches_text("*.rs", "main.rs"));
+        assert!(matches_text("*.rs", ".rs"));
+        assert!(!matches_text("*.rs", "main.rsx"));
+        assert!(matches_text("a*b*c", "aXXbYYc"));
+        assert!(!matches_text("a*b*c", "aXXbYY"));
+        assert!(matches_text("?x", "ax"));
+        assert!(!matches_text("?x", "x"));
+        assert!(matches_text("*", ""));
+        assert!(matches_text("**", "a/b"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches_text("[abc]", "b"));
+        assert!(!matches_text("[abc]", "d"));
+        assert!(matches_text("[a-c]x", "cx"));
+        assert!(matches_text("[!a-c]", "d"));
+        assert!(!matches_text("[^a-c]", "a"));
+        assert!(matches_text("[]]", "]"));
+        // with no closing `]` it's just a `[`
+        assert!(matches_text("[ab", "[ab"));
+    }
+
+    #[test]
+    fn literal_words() {
+        assert!(matches_pattern("a*b", "a*b", &mut PatternCache::default()));
+        assert!(!matches_pattern("a*b", "axb", &mut PatternCache::default()));
+    }
+
+    #[test]
+    fn many_stars() {
+        // backtracking into each `*` in turn would take far too long here
+        let text = "a".repeat(64);
+        assert!(!matches_text(&format!("{}b", "a*".repeat(32)), &text));
+        assert!(matches_text(&"a*".repeat(32), &text));
+    }
+
+    #[test]
+    fn cache_gives_the_same_answers() {
+        let mut cache = PatternCache::default();
+        for _ in 0..3 {
+            assert!(matches_pattern(&pattern("*.rs"), "lib.rs", &mut cache));
+            assert!(!matches_pattern(&pattern("*.rs"), "lib.c", &mut cache));
+        }
+    }
+}
@@ -0,0 +1,135 @@
+// Here-documents: `cmd <<TAG` reads the lines after the command up to one
+// consisting of TAG and feeds them to cmd's stdin. `<<-TAG` strips leading
+// tabs from the body and terminator. Unless any of the tag is quoted
+// ('TAG', "TAG", \TAG), the body's parameters, `$(command)`, `command` and
+// `$((expr))` are expanded as the command runs, as they would be inside
+// double quotes, and `\` escapes `$`, `` ` ``, `\` and a newline.
+
+use crate::{ParseError, Token, expand, read_backquoted, read_subcommand};
+
+struct Operator<'t> {
+    delimiter: &'t str,
+    strip_tabs: bool,
+}
+
+fn operator(tokens: &[Token], i: usize) -> Option<Result<Operator<'_>, ParseError<'_>>> {
+    let strip_tabs = match &tokens[i] {
+        Token::Op(op) if op == "<<" => false,
+        Token::Op(op) if op == "<<-" => true,
+        _ => return None,
+    };
+
+    Some(match tokens.get(i + 1) {
+        Some(Token::Word(delimiter)) => Ok(Operator {
+            delimiter,
+            strip_tabs,
+        }),
+        other => Err(ParseError::at(other)),
+    })
+}
+
+// Reads the body of each here-document on the command line, in order,
+// taking lines from `next_line` (None at end of input). `quoted` tells
+// whether the token at an index had any quoting.
+pub fn read_bodies(
+    tokens: &[Token],
+    quoted: impl Fn(usize) -> bool,
+    mut next_line: impl FnMut() -> Option<String>,
+) -> Result<Vec<String>, ParseError<'_>> {
+    let mut bodies = Vec::new();
+
+    for i in 0..tokens.len() {
+        let Some(op) = operator(tokens, i) else {
+            continue;
+        };
+        let op = op?;
+
+        let mut body = String::new();
+        loop {
+            let Some(line) = next_line() else {
+                eprintln!(
+                    "oxide: warning: here-document delimited by end-of-file (wanted `{}')",
+                    op.delimiter
+                );
+                break;
+            };
+            let line = if op.strip_tabs {
+                line.trim_start_matches('\t')
+            } else {
+                &line
+            };
+            if line.trim_end_matches('\n') == op.delimiter {
+                break;
+            }
+            body.push_str(line);
+            if !line.ends_with('\n') {
+                body.push('\n');
+            }
+        }
+        if quoted(i + 1) {
+            bodies.push(body);
+        } else {
+            bodies.push(mark(&body));
+        }
+    }
+
+    Ok(bodies)
+}
+
+// Replaces each here-document operator's delimiter with its body, leaving
+// `<<` followed by the text, the form the redirection parser understands.
+pub fn substitute(tokens: Vec<Token>, bodies: Vec<String>) -> Vec<Token> {
+    let mut out = Vec::with_capacity(tokens.len());
+    let mut bodies = bodies.into_iter();
+    let mut tokens = tokens.into_iter();
+
+    while let Some(token) = tokens.next() {
+        if token.is_op("<<") || token.is_op("<<-") {
+            tokens.next();
+            out.push(Token::Op("<<".to_string()));
+            out.push(Token::Word(bodies.next().unwrap_or_default()));
+        } else {
+            out.push(token);
+        }
+    }
+    out
+}
+
+// Marks the expansions in an unquoted body the way the tokenizer marks
+// them in a double-quoted word, and takes out the `\` that escape.
+fn mark(body: &str) -> String {
+    let marked = format!("{}{}", expand::MARKER, expand::QUOTED);
+    let mut out = String::with_capacity(body.len());
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next_if(|&n| matches!(n, '$' | '`' | '\\' | '\n')) {
+                Some('\n') => {}
+                Some(next) => out.push(next),
+                None => out.push(c),
+            },
+            // `$~` is nothing to expand
+            '$' if chars.peek() == Some(&'~') => out.push(c),
+            '$' => {
+                out.push_str(&marked);
+                // a command substitution is kept whole for expand to run
+                if chars.next_if_eq(&'(').is_some() {
+                    out.push('(');
+                    out.push_str(&read_subcommand(&mut chars));
+                    out.push(')');
+                } else if chars.next_if_eq(&'$').is_some() {
+                    // `$$`, rather than a `$` and another expansion
+                    out.push('$');
+                }
+            }
+            '`' => {
+                out.push_str(&marked);
+                out.push('(');
+                out.push_str(&read_backquoted(&mut chars, true));
+                out.push(')');
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,244 @@
+pub struct Process {
+    pub pid: i32,
+    pub status: Option<i32>,
+    pub stopped: bool,
+}
+
+pub struct Job {
+    pub id: usize,
+    // the process group the job runs in under job control: its first
+    // process's pid
+    pub pgid: i32,
+    pub processes: Vec<Process>,
+    pub command: String,
+}
+
+impl Job {
+    pub fn is_done(&self) -> bool {
+        self.processes.iter().all(|p| p.status.is_some())
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.processes.iter().any(|p| p.stopped)
+    }
+
+    // How `jobs` and completion notices describe the job.
+    pub fn state(&self) -> String {
+        match self.status() {
+            None if self.is_stopped() => "Stopped".to_string(),
+            None => "Running".to_string(),
+            Some(0) => "Done".to_string(),
+            Some(status) => format!("Exit {status}"),
+        }
+    }
+
+    // A job's status is the status of its last process, once everything
+    // in it has finished.
+    pub fn status(&self) -> Option<i32> {
+        if self.is_done() {
+            self.processes.last().and_then(|p| p.status)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct JobTable {
+    jobs: Vec<Job>,
+    current: Option<usize>,
+    previous: Option<usize>,
+}
+
+impl Default for JobTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JobTable {
+    pub fn new() -> Self {
+        JobTable {
+            jobs: Vec::new(),
+            current: None,
+            previous: None,
+        }
+    }
+
+    pub fn add(&mut self, pids: &[i32], command: String) -> usize {
+        let id = self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1;
+        let processes = pids
+            .iter()
+            .map(|&pid| Process {
+                pid,
+                status: None,
+                stopped: false,
+            })
+            .collect();
+        self.jobs.push(Job {
+            id,
+            pgid: pids.first().copied().unwrap_or(0),
+            processes,
+            command,
+        });
+        self.previous = self.current;
+        self.current = Some(id);
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        let pos = self.jobs.iter().position(|j| j.id == id)?;
+        let job = self.jobs.remove(pos);
+
+        if self.current == Some(id) {
+            self.current = self.previous.take();
+        } else if self.previous == Some(id) {
+            self.previous = None;
+        }
+        if self.previous.is_none() {
+            self.previous = self
+                .jobs
+                .iter()
+                .rev()
+                .map(|j| j.id)
+                .find(|&j| Some(j) != self.current);
+        }
+        if self.current.is_none() {
+            self.current = self.previous.take();
+        }
+
+        Some(job)
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|j| j.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|j| j.id == id)
+    }
+
+    pub fn find_pid(&self, pid: i32) -> Option<usize> {
+        self.jobs
+            .iter()
+            .find(|j| j.processes.iter().any(|p| p.pid == pid))
+            .map(|j| j.id)
+    }
+
+    pub fn record_exit(&mut self, pid: i32, status: i32) {
+        for job in &mut self.jobs {
+            if let Some(p) = job.processes.iter_mut().find(|p| p.pid == pid) {
+                p.status = Some(status);
+                p.stopped = false;
+                return;
+            }
+        }
+    }
+
+    // Marks every unfinished process of a job as stopped (or running again).
+    // A stop signal goes to the whole job, so its processes stop together.
+    pub fn set_stopped(&mut self, id: usize, stopped: bool) {
+        if let Some(job) = self.get_mut(id) {
+            for p in job.processes.iter_mut().filter(|p| p.status.is_none()) {
+                p.stopped = stopped;
+            }
+        }
+    }
+
+    // Reaps one finished child and records it against its job. Returns false
+    // when there was nothing to reap (no children, or none ready when not
+    // blocking).
+    pub fn reap(&mut self, block: bool) -> bool {
+        let mut raw = 0;
+        let flags = if block { 0 } else { libc::WNOHANG };
+        let pid = unsafe { libc::waitpid(-1, &mut raw, flags) };
+        if pid <= 0 {
+            return false;
+        }
+        self.record_exit(pid, decode_status(raw));
+        true
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn previous(&self) -> Option<usize> {
+        self.previous
+    }
+
+    // Resolves a jobspec to a job id:
+    //   %n        job number n
+    //   %+ %% %   the current job
+    //   %-        the previous job
+    //   %string   the job whose command starts with string
+    //   %?string  the job whose command contains string
+    pub fn resolve(&self, spec: &str) -> Result<usize, String> {
+        let no_such_job = || format!("{spec}: no such job");
+
+        let Some(body) = spec.strip_prefix('%') else {
+            return Err(no_such_job());
+        };
+
+        match body {
+            "" | "+" | "%" => self.current.ok_or_else(no_such_job),
+            "-" => self.previous.or(self.current).ok_or_else(no_such_job),
+            _ if body.bytes().all(|b| b.is_ascii_digit()) => body
+                .parse()
+                .ok()
+                .filter(|&id| self.get(id).is_some())
+                .ok_or_else(no_such_job),
+            _ => {
+                let matches: Vec<usize> = match body.strip_prefix('?') {
+                    Some(needle) => self
+                        .jobs
+                        .iter()
+                        .filter(|j| j.command.contains(needle))
+                        .map(|j| j.id)
+                        .collect(),
+                    None => self
+                        .jobs
+                        .iter()
+                        .filter(|j| j.command.starts_with(body))
+                        .map(|j| j.id)
+                        .collect(),
+                };
+
+                match matches.as_slice() {
+                    [id] => Ok(*id),
+                    [] => Err(no_such_job()),
+                    _ => Err(format!("{spec}: ambiguous job spec")),
+                }
+            }
+        }
+    }
+
+    // The marker shown next to a job in listings: '+' for the current job,
+    // '-' for the previous one.
+    pub fn marker(&self, id: usize) -> char {
+        if self.current == Some(id) {
+            '+'
+        } else if self.previous == Some(id) {
+            '-'
+        } else {
+            ' '
+        }
+    }
+}
+
+pub fn decode_status(raw: i32) -> i32 {
+    if libc::WIFEXITED(raw) {
+        libc::WEXITSTATUS(raw)
+    } else if libc::WIFSIGNALED(raw) {
+        128 + libc::WTERMSIG(raw)
+    } else {
+        raw
+    }
+}
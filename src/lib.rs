@@ -1,323 +1,2954 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
 use std::env;
-use std::io::Write;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, PipeReader, PipeWriter, Seek, SeekFrom, Write};
+use std::iter::Peekable;
+use std::ops::Range;
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
 use std::path::Path;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+mod alias;
+mod arith;
+mod brace;
 mod built_in_commands;
+pub mod cli;
+mod command_cache;
+pub mod completion;
+mod conditional;
+pub mod env_file;
+pub mod expand;
+mod function_env;
+mod glob;
+pub mod heredoc;
+pub mod jobs;
+mod path_correction;
+pub mod process_sub;
+pub mod project_env;
+pub mod prompt;
+pub mod rc_cache;
+pub mod signals;
+mod spawn;
+pub mod terminal;
+pub mod traps;
+pub mod universal;
+pub mod vars;
+pub mod venv;
+
+use command_cache::CommandCache;
+use completion::CompSpec;
+use glob::PatternCache;
+use jobs::{Job, JobTable};
+use project_env::ProjectEnv;
+use traps::Traps;
+use universal::UniversalVars;
+use vars::Variables;
+use venv::AutoVenv;
+
+#[derive(Default)]
+pub struct Shell {
+    pub jobs: JobTable,
+    pub aliases: BTreeMap<String, String>,
+    // each function's body: its group and any redirections after it
+    pub functions: BTreeMap<String, Vec<Token>>,
+    // $1, $2, ...: the arguments of the function or script that's running
+    pub positional: Vec<String>,
+    // $0: the script that's running, or the shell's own name
+    pub name: String,
+    // $$: the shell's pid, which its subshells keep
+    pub pid: i32,
+    // $!: the last process started in the background
+    pub last_background: Option<i32>,
+    // reading commands from a terminal, or told to act as if it were
+    pub interactive: bool,
+    pub completions: BTreeMap<String, CompSpec>,
+    pub project_env: ProjectEnv,
+    pub venv: AutoVenv,
+    pub universal: UniversalVars,
+    pub options: ShellOptions,
+    pub vars: Variables,
+    pub traps: Traps,
+    // each job gets its own process group and the terminal while it's in
+    // the foreground; only for interactive shells on a terminal
+    pub job_control: bool,
+    // a forked copy of the shell, running a `( )`, a `$( )` or the like
+    pub subshell: bool,
+    // the status of the last pipeline, `$?`
+    pub status: i32,
+    // the status of each of its stages, `$PIPESTATUS`
+    pub pipestatus: Vec<i32>,
+    // set by `exit`: the shell stops reading commands and exits with it
+    pub exit_status: Option<i32>,
+    // set by `break` and `continue`: what's running stops until the loop
+    // they're meant for is reached
+    pub loop_control: Option<LoopControl>,
+    // how many loops are running, which `break 2` and the like can't go past
+    pub loop_depth: usize,
+    // set by `return`: what's running stops until the function it's in
+    // does, which takes `$?` as its status
+    pub returning: bool,
+    // how many functions are running; `return` only works inside one
+    pub function_depth: usize,
+    // how many conditions are running: a loop's, or the pipelines of an
+    // `&&` or `||` list before its last, whose failure `set -e` ignores
+    pub condition_depth: usize,
+    // the status of the last `$(...)` in the command being expanded, which
+    // a command of nothing but assignments takes as its own
+    pub substitution_status: Option<i32>,
+    commands: CommandCache,
+    patterns: PatternCache,
+}
+
+// `break n` and `continue n`, counting out from the innermost loop.
+#[derive(Clone, Copy)]
+pub enum LoopControl {
+    Break(usize),
+    Continue(usize),
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        let mut shell = Self {
+            name: "oxide".to_string(),
+            pid: std::process::id() as i32,
+            ..Self::default()
+        };
+        shell.functions = function_env::import();
+        shell.universal.sync();
+        shell
+    }
+
+    // Whether `exit`, `break`, `continue` or `return` has stopped the
+    // commands that are running.
+    fn unwinding(&self) -> bool {
+        self.exit_status.is_some() || self.loop_control.is_some() || self.returning
+    }
+
+    // Reaps background jobs that have finished. Interactive shells then
+    // report them (`[1]+  Done                    sleep 5`) and forget them;
+    // otherwise they're kept for `wait` to collect.
+    pub fn reap_jobs(&mut self, notify: bool) {
+        while self.jobs.reap(false) {}
+        if !notify {
+            return;
+        }
+
+        let done: Vec<usize> = self
+            .jobs
+            .iter()
+            .filter(|j| j.is_done())
+            .map(|j| j.id)
+            .collect();
+        for id in done {
+            let marker = self.jobs.marker(id);
+            if let Some(job) = self.jobs.remove(id) {
+                println!("[{id}]{marker}  {:<24}{}", job.state(), job.command);
+            }
+        }
+    }
+
+    // Runs the trap commands for the signals that arrived since the last
+    // call. `$?` is left as it was, unless a trap exits the shell.
+    pub fn run_traps(&mut self) {
+        for signal in signals::take_pending() {
+            let Some(action) = self.traps.get(signal).map(str::to_string) else {
+                continue;
+            };
+            let status = self.status;
+            eval(self, &action);
+            if self.exit_status.is_some() {
+                return;
+            }
+            self.status = status;
+        }
+    }
+
+    // For a forked child that goes on to run commands itself: it has no
+    // jobs of its own and does no job control, and its traps are reset,
+    // except that ignored signals stay ignored.
+    pub fn enter_subshell(&mut self) {
+        self.jobs = JobTable::new();
+        self.job_control = false;
+        self.subshell = true;
+        signals::uninstall();
+        self.traps.reset_for_subshell();
+    }
+
+    // Runs the EXIT trap and returns the status the shell exits with: the
+    // one given to `exit` (or the last command's), unless the trap itself
+    // calls `exit`.
+    pub fn run_exit_trap(&mut self) -> i32 {
+        let status = self.exit_status.take().unwrap_or(self.status);
+        if let Some(action) = self.traps.take_exit() {
+            eval(self, &action);
+        }
+        self.exit_status.unwrap_or(status)
+    }
+}
+
+// Runs a line the way it would run if typed at the prompt, except that
+// here-documents have no further input to take their bodies from.
+pub fn eval(shell: &mut Shell, line: &str) -> i32 {
+    match Script::parse(line, || None) {
+        Ok(script) => script.execute(shell),
+        Err(e) => {
+            eprintln!("oxide: {e}");
+            shell.status = 2;
+            2
+        }
+    }
+}
+
+// Input parsed and ready to run: its tokens, with the bodies of any
+// here-documents in place, already checked for syntax errors.
+pub struct Script {
+    tokens: Vec<Token>,
+}
+
+impl Script {
+    // Tokenizes and parses `input`, taking any lines its here-documents
+    // need from `more` (None at end of input).
+    pub fn parse(input: &str, more: impl FnMut() -> Option<String>) -> Result<Self, SyntaxError> {
+        let (tokens, open) = lex(input);
+        if let Some(quote @ ('\'' | '"')) = open {
+            return Err(SyntaxError {
+                message: format!("unexpected end of file while looking for matching `{quote}'"),
+                offset: input.trim_end().len(),
+            });
+        }
+        let (tokens, spans): (Vec<Token>, Vec<Range<usize>>) = tokens.into_iter().unzip();
+        // where in the input an error was found: at its token, or at the
+        // end if it ran out of tokens
+        let locate = |tokens: &[Token], e: ParseError| {
+            let offset = match e {
+                ParseError::Unexpected(token) => tokens
+                    .iter()
+                    .position(|t| std::ptr::eq(t, token))
+                    .map(|i| spans[i].start),
+                _ => None,
+            };
+            SyntaxError {
+                message: e.to_string(),
+                offset: offset.unwrap_or(input.trim_end().len()),
+            }
+        };
+
+        let quoted = |i: usize| input[spans[i].clone()].contains(['\'', '"', '\\']);
+        let bodies = heredoc::read_bodies(&tokens, quoted, more).map_err(|e| locate(&tokens, e))?;
+        // one token for one, so the spans still line up
+        let tokens = heredoc::substitute(tokens, bodies);
+        if let Err(e) = CommandList::parse(&tokens) {
+            return Err(locate(&tokens, e));
+        }
+        Ok(Script { tokens })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    // Aliases are expanded as it starts, so one defined by the script
+    // applies from the next script on.
+    pub fn execute(&self, shell: &mut Shell) -> i32 {
+        let tokens = alias::expand(self.tokens.clone(), &shell.aliases);
+        match CommandList::parse(&tokens) {
+            Ok(list) => list.execute(shell),
+            // an alias's value can be a syntax error where it's used
+            Err(e) => {
+                eprintln!("oxide: {e}");
+                shell.status = 2;
+                2
+            }
+        }
+    }
+}
+
+// What the parser can find wrong with a command line.
+pub enum ParseError<'a> {
+    Unexpected(&'a Token),
+    // the line ended where something else had to come first
+    UnexpectedNewline,
+    // a `(` or `{` that was never closed
+    UnexpectedEof,
+}
+
+impl<'a> ParseError<'a> {
+    // The token found where something else was wanted, or the end of the
+    // line if there wasn't one.
+    pub(crate) fn at(token: Option<&'a Token>) -> Self {
+        token.map_or(ParseError::UnexpectedNewline, ParseError::Unexpected)
+    }
+}
+
+impl fmt::Display for ParseError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Unexpected(token) => {
+                write!(f, "syntax error near unexpected token `{token}'")
+            }
+            ParseError::UnexpectedNewline => {
+                f.write_str("syntax error near unexpected token `newline'")
+            }
+            ParseError::UnexpectedEof => f.write_str("syntax error: unexpected end of file"),
+        }
+    }
+}
+
+// A ParseError, along with the byte offset into the input it points at.
+pub struct SyntaxError {
+    pub message: String,
+    pub offset: usize,
+}
+
+impl SyntaxError {
+    // The message, followed by the line of `input` the error is on with a
+    // caret under the spot:
+    //
+    //   syntax error near unexpected token `|'
+    //     echo hi | | cat
+    //               ^
+    pub fn render(&self, input: &str) -> String {
+        let start = input[..self.offset].rfind('\n').map_or(0, |i| i + 1);
+        let end = input[start..].find('\n').map_or(input.len(), |i| start + i);
+        let column = input[start..self.offset].chars().count();
+        format!(
+            "{}\n  {}\n  {}^",
+            self.message,
+            &input[start..end],
+            " ".repeat(column)
+        )
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+// Options toggled with `set -o name` / `set +o name`, or the single-letter
+// flag where there is one.
+#[derive(Default)]
+pub struct ShellOptions {
+    // -e: a failing command ends the shell
+    pub errexit: bool,
+    // `**` in a pattern matches any number of directories
+    pub globstar: bool,
+    // Ctrl-D at an interactive prompt doesn't end the shell
+    pub ignoreeof: bool,
+    // -C: `>` refuses to overwrite an existing file; `>|` still does
+    pub noclobber: bool,
+    // -u: expanding a variable that isn't set is an error
+    pub nounset: bool,
+    // a pattern that matches nothing is removed rather than kept as it is
+    pub nullglob: bool,
+    // a pipeline fails if any stage does, not just the last
+    pub pipefail: bool,
+    // -x: commands are printed to stderr before they run
+    pub xtrace: bool,
+}
+
+impl ShellOptions {
+    pub const NAMES: &[&str] = &[
+        "errexit",
+        "globstar",
+        "ignoreeof",
+        "noclobber",
+        "nounset",
+        "nullglob",
+        "pipefail",
+        "xtrace",
+    ];
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "errexit" => Some(&mut self.errexit),
+            "globstar" => Some(&mut self.globstar),
+            "ignoreeof" => Some(&mut self.ignoreeof),
+            "noclobber" => Some(&mut self.noclobber),
+            "nounset" => Some(&mut self.nounset),
+            "nullglob" => Some(&mut self.nullglob),
+            "pipefail" => Some(&mut self.pipefail),
+            "xtrace" => Some(&mut self.xtrace),
+            _ => None,
+        }
+    }
+
+    // `$-`: the single-letter flags that are on.
+    pub fn flags(&self) -> String {
+        [
+            ('C', self.noclobber),
+            ('e', self.errexit),
+            ('u', self.nounset),
+            ('x', self.xtrace),
+        ]
+        .into_iter()
+        .filter_map(|(flag, on)| on.then_some(flag))
+        .collect()
+    }
+
+    // The option a single-letter `set` flag stands for.
+    pub fn flag_mut(&mut self, flag: char) -> Option<&mut bool> {
+        match flag {
+            'C' => Some(&mut self.noclobber),
+            'e' => Some(&mut self.errexit),
+            'u' => Some(&mut self.nounset),
+            'x' => Some(&mut self.xtrace),
+            _ => None,
+        }
+    }
+}
 
 pub enum ShellCommand<'a> {
-    Exit,
+    Exit(Vec<&'a str>),
     Echo(Vec<&'a str>),
     Pwd,
-    Type(&'a str, Vec<&'a str>),
-    Cd(&'a str, Vec<&'a str>),
+    Type(&'a str),
+    Cd(Option<&'a str>),
+    Wait(Vec<&'a str>),
+    Jobs(Vec<&'a str>),
+    Fg(Vec<&'a str>),
+    Bg(Vec<&'a str>),
+    Disown(Vec<&'a str>),
+    Kill(Vec<&'a str>),
+    Complete(Vec<&'a str>),
+    Compgen(Vec<&'a str>),
+    Dotenv(Vec<&'a str>),
+    Set(Vec<&'a str>),
+    Export(Vec<&'a str>),
+    Local(Vec<&'a str>),
+    Shift(Vec<&'a str>),
+    Alias(Vec<&'a str>),
+    Let(Vec<&'a str>),
+    Break(Vec<&'a str>),
+    Return(Vec<&'a str>),
+    Continue(Vec<&'a str>),
+    // `(( expr ))`
+    Arith(&'a str),
+    Unalias(Vec<&'a str>),
+    Unset(Vec<&'a str>),
+    Trap(Vec<&'a str>),
+    Exec(Vec<&'a str>),
     External(&'a str, Vec<&'a str>),
+    Background(&'a str, Vec<&'a str>),
     Empty,
 }
 
+// Per-command state for builtins. Output goes through a BufWriter so a
+// builtin producing many lines costs one write when the command finishes
+// rather than one per line.
+pub struct ExecutionContext<'w> {
+    pub out: BufWriter<Box<dyn Write + 'w>>,
+    err: Option<File>,
+}
+
+impl<'w> ExecutionContext<'w> {
+    // None when a redirection couldn't be opened; the error has already
+    // been reported and the builtin should not run.
+    fn new(redirects: &[Redirect], stdout: Box<dyn Write + 'w>) -> Option<Self> {
+        let mut streams = apply_redirects(redirects, Streams::new())?;
+        let out: Box<dyn Write + 'w> = match streams.remove(&1) {
+            Some(fd) => Box::new(File::from(fd)),
+            None => stdout,
+        };
+        Some(ExecutionContext {
+            out: BufWriter::new(out),
+            err: streams.remove(&2).map(File::from),
+        })
+    }
+
+    // Reports an error on the builtin's stderr, wherever that was sent.
+    fn error(&mut self, message: &str) {
+        match &mut self.err {
+            Some(file) => {
+                let _ = writeln!(file, "{message}");
+            }
+            None => eprintln!("{message}"),
+        }
+    }
+
+    // Flushes the buffered output and folds any write error into the
+    // command's status. A reader that went away (EPIPE) gives the status a
+    // SIGPIPE death would have produced; a script or subshell then exits
+    // quietly, as the signal would have ended it if the shell didn't
+    // ignore it, while an interactive shell carries on.
+    fn finish(mut self, shell: &mut Shell, result: io::Result<i32>) -> i32 {
+        match result.and_then(|status| self.out.flush().map(|_| status)) {
+            Ok(status) => status,
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                let status = 128 + libc::SIGPIPE;
+                if !shell.interactive || shell.subshell {
+                    shell.exit_status = Some(status);
+                }
+                status
+            }
+            Err(e) => {
+                eprintln!("oxide: write error: {e}");
+                1
+            }
+        }
+    }
+}
+
+// One redirection operator. A command's redirections are applied left to
+// right, so `> out.log 2>&1` sends both streams to the file while
+// `2>&1 > out.log` leaves stderr on the terminal.
+#[derive(Clone)]
 pub enum Redirect<'a> {
-    AppendStdout(&'a str),
-    AppendStderr(&'a str),
-    Stderr(&'a str),
-    Stdout(&'a str),
+    // n> file, n>> file, n>| file, n< file
+    File {
+        fd: usize,
+        path: &'a str,
+        mode: OpenMode,
+    },
+    // n>&m and n<&m: fd n becomes a copy of fd m
+    Dup {
+        fd: usize,
+        target: usize,
+    },
+    // n<< followed by a here-document body: fd n reads the text
+    Text {
+        fd: usize,
+        text: &'a str,
+    },
+    // n<<< word: fd reads the word and a newline
+    Word {
+        fd: usize,
+        word: &'a str,
+    },
+}
+
+#[derive(Clone, Copy)]
+pub enum OpenMode {
+    Read,
+    Truncate,
+    Append,
+    // `>` under noclobber: only creates files
+    Create,
+    // `>|`: truncates even under noclobber
+    Clobber,
 }
 
+// The descriptors a command about to run gets, by fd number. Anything
+// missing is left as it would otherwise be (usually the shell's own).
+type Streams = BTreeMap<usize, OwnedFd>;
+
 impl<'a> ShellCommand<'a> {
-    pub fn parse(tokens: &'a [&'a str]) -> Self {
-        match tokens[0] {
-            "exit" => ShellCommand::Exit,
+    pub fn parse(tokens: &[&'a str]) -> Self {
+        let Some(&first) = tokens.first() else {
+            return ShellCommand::Empty;
+        };
+
+        match first {
+            "exit" => ShellCommand::Exit(tokens[1..].to_vec()),
             "echo" => ShellCommand::Echo(tokens[1..].to_vec()),
             "pwd" => ShellCommand::Pwd,
             "type" => tokens
                 .get(1)
-                .map(|x| ShellCommand::Type(x, tokens[1..].to_vec()))
+                .map(|x| ShellCommand::Type(x))
                 .unwrap_or(ShellCommand::Empty),
 
-            "cd" => tokens
-                .get(1)
-                .map(|x| ShellCommand::Cd(x, tokens[1..].to_vec()))
-                .unwrap_or(ShellCommand::Empty),
+            "cd" => ShellCommand::Cd(tokens.get(1).copied()),
+
+            "wait" => ShellCommand::Wait(tokens[1..].to_vec()),
+            "jobs" => ShellCommand::Jobs(tokens[1..].to_vec()),
+            "fg" => ShellCommand::Fg(tokens[1..].to_vec()),
+            "bg" => ShellCommand::Bg(tokens[1..].to_vec()),
+            "disown" => ShellCommand::Disown(tokens[1..].to_vec()),
+            "kill" => ShellCommand::Kill(tokens[1..].to_vec()),
+            "complete" => ShellCommand::Complete(tokens[1..].to_vec()),
+            "compgen" => ShellCommand::Compgen(tokens[1..].to_vec()),
+            "dotenv" => ShellCommand::Dotenv(tokens[1..].to_vec()),
+            "set" => ShellCommand::Set(tokens[1..].to_vec()),
+            "export" => ShellCommand::Export(tokens[1..].to_vec()),
+            "local" => ShellCommand::Local(tokens[1..].to_vec()),
+            "shift" => ShellCommand::Shift(tokens[1..].to_vec()),
+            "alias" => ShellCommand::Alias(tokens[1..].to_vec()),
+            "let" => ShellCommand::Let(tokens[1..].to_vec()),
+            "break" => ShellCommand::Break(tokens[1..].to_vec()),
+            "return" => ShellCommand::Return(tokens[1..].to_vec()),
+            "continue" => ShellCommand::Continue(tokens[1..].to_vec()),
+            arith if arith.starts_with("((") && arith.ends_with("))") => {
+                ShellCommand::Arith(&arith[2..arith.len() - 2])
+            }
+            "unalias" => ShellCommand::Unalias(tokens[1..].to_vec()),
+            "unset" => ShellCommand::Unset(tokens[1..].to_vec()),
+            "trap" => ShellCommand::Trap(tokens[1..].to_vec()),
+            "exec" => ShellCommand::Exec(tokens[1..].to_vec()),
 
             cmd => ShellCommand::External(cmd, tokens[1..].to_vec()),
         }
     }
 
-    pub fn execute(self) -> bool {
-        // capture output buffer
+    pub fn execute(self, shell: &mut Shell) -> i32 {
+        self.execute_to(shell, &[], Box::new(io::stdout()))
+    }
 
+    // Runs the command with its redirections and with builtin output going
+    // to `stdout` instead of the shell's own, which is how builtins take
+    // part in pipelines. Returns the command's exit status.
+    fn execute_to<'w>(
+        self,
+        shell: &mut Shell,
+        redirects: &[Redirect],
+        stdout: Box<dyn Write + 'w>,
+    ) -> i32 {
         match self {
-            ShellCommand::Exit => return false,
+            ShellCommand::Exit(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let status = built_in_commands::exit(shell, &args, &mut ctx);
+                ctx.finish(shell, Ok(status))
+            }
 
             ShellCommand::Echo(args) => {
-                let (real_args, redirect) = split_redirect(&args);
-                let mut output = real_args.join(" ");
-                match &redirect {
-                    Some(Redirect::Stderr(file)) => {
-                        println!("{output}");
-                        write_output("", Some(Redirect::Stderr(file)));
-                    }
-                    Some(Redirect::Stdout(file)) => {
-                        output.push('\n');
-                        write_output(&output, Some(Redirect::Stdout(file)));
-                    }
-                    Some(Redirect::AppendStdout(file)) => {
-                        output.push('\n');
-                        write_output(&output, Some(Redirect::AppendStdout(file)));
-                    }
-                    Some(Redirect::AppendStderr(file)) => {
-                        println!("{output}");
-                        write_output("", Some(Redirect::AppendStderr(file)));
-                    }
-                    None => println!("{output}"),
-                }
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let result = writeln!(ctx.out, "{}", args.join(" "));
+                ctx.finish(shell, result.map(|_| 0))
             }
 
             ShellCommand::Pwd => {
-                let path = env::current_dir().unwrap();
-                let output = format!("{}", path.display());
-                write_output(&output, None);
-            }
-
-            ShellCommand::Type(name, args) => {
-                let output;
-                let (_, redirect) = split_redirect(&args);
-                if built_in_commands::is_builtin(name) {
-                    output = format!("{name} is a shell builtin");
-                } else if let Some(exe) = pathsearch::find_executable_in_path(name) {
-                    output = format!("{name} is {}", exe.display());
-                } else {
-                    output = format!("{name}: not found");
-                }
-
-                match redirect {
-                    Some(Redirect::Stderr(file)) => {
-                        println!("{output}");
-                        write_output("", Some(Redirect::Stderr(file)));
-                    }
-                    Some(Redirect::Stdout(file)) => {
-                        write_output(&output, Some(Redirect::Stdout(file)));
-                    }
-                    Some(Redirect::AppendStdout(file)) => {
-                        write_output(&output, Some(Redirect::AppendStdout(file)));
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                match env::current_dir() {
+                    Ok(path) => {
+                        let result = writeln!(ctx.out, "{}", path.display());
+                        ctx.finish(shell, result.map(|_| 0))
                     }
-                    Some(Redirect::AppendStderr(file)) => {
-                        write_output(&output, Some(Redirect::AppendStderr(file)));
+                    Err(e) => {
+                        ctx.error(&format!(
+                            "pwd: error retrieving current directory: {}",
+                            error_text(&e)
+                        ));
+                        1
                     }
-                    None => println!("{output}"),
                 }
             }
 
-            ShellCommand::Cd(path, args) => {
-                let (_, redirect) = split_redirect(&args);
-                let target = if path == "~" {
-                    env::var("HOME").unwrap_or_else(|_| "/".to_string())
+            ShellCommand::Type(name) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let result = if let Some(value) = shell.aliases.get(name) {
+                    writeln!(ctx.out, "{name} is aliased to `{value}'").map(|_| 0)
+                } else if shell.functions.contains_key(name) {
+                    writeln!(ctx.out, "{name} is a function").map(|_| 0)
+                } else if built_in_commands::is_builtin(name) {
+                    writeln!(ctx.out, "{name} is a shell builtin").map(|_| 0)
+                } else if let Some(exe) = shell.commands.resolve(name) {
+                    writeln!(ctx.out, "{name} is {}", exe.display()).map(|_| 0)
                 } else {
-                    path.to_string()
+                    writeln!(ctx.out, "{name}: not found").map(|_| 1)
                 };
+                ctx.finish(shell, result)
+            }
 
-                if Path::new(&target).is_dir() {
-                    if let Err(e) = env::set_current_dir(&target) {
-                        let error_msg = format!("cd: {}", e);
-                        match &redirect {
-                            Some(Redirect::Stderr(file)) => {
-                                write_output(&error_msg, Some(Redirect::Stderr(file)));
-                            }
-                            Some(Redirect::Stdout(file)) => {
-                                write_output(&error_msg, Some(Redirect::Stdout(file)));
-                            }
-                            Some(Redirect::AppendStdout(file)) => {
-                                write_output(&error_msg, Some(Redirect::AppendStdout(file)));
-                            }
-                            Some(Redirect::AppendStderr(file)) => {
-                                write_output(&error_msg, Some(Redirect::AppendStderr(file)));
-                            }
-                            None => {
-                                eprintln!("{error_msg}");
-                            }
-                        }
+            ShellCommand::Cd(path) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                // without HOME a bare `cd` has nowhere to go
+                let target = match (path, env::var("HOME")) {
+                    (None, Ok(home)) => home,
+                    (None, Err(_)) => {
+                        ctx.error("cd: HOME not set");
+                        return 1;
                     }
+                    (Some(path), _) => path.to_string(),
+                };
+                // scripts get the directory they asked for or an error,
+                // never a guess
+                let target = if Path::new(&target).is_dir() || !shell.interactive {
+                    target
                 } else {
-                    let error_msg = format!("cd: {}: No such file or directory", target);
-                    match &redirect {
-                        Some(Redirect::Stderr(file)) => {
-                            write_output(&error_msg, Some(Redirect::Stderr(file)));
-                        }
-                        Some(Redirect::Stdout(file)) => {
-                            write_output(&error_msg, Some(Redirect::Stdout(file)));
-                        }
-                        Some(Redirect::AppendStdout(file)) => {
-                            write_output(&error_msg, Some(Redirect::AppendStdout(file)));
-                        }
-                        Some(Redirect::AppendStderr(file)) => {
-                            write_output(&error_msg, Some(Redirect::AppendStderr(file)));
+                    path_correction::corrected_cd_target(&target, |fixed| ctx.error(fixed))
+                        .unwrap_or(target)
+                };
+
+                let status = if !Path::new(&target).is_dir() {
+                    ctx.error(&format!("cd: {target}: No such file or directory"));
+                    1
+                } else {
+                    let previous = env::current_dir();
+                    match env::set_current_dir(&target) {
+                        Ok(()) => {
+                            // for `~-` and `~+`
+                            if let Ok(previous) = previous {
+                                shell.vars.set("OLDPWD", &previous.to_string_lossy());
+                            }
+                            if let Ok(current) = env::current_dir() {
+                                shell.vars.set("PWD", &current.to_string_lossy());
+                            }
+                            0
                         }
-                        None => {
-                            eprintln!("{error_msg}");
+                        Err(e) => {
+                            ctx.error(&format!("cd: {target}: {}", error_text(&e)));
+                            1
                         }
                     }
-                }
+                };
+                ctx.finish(shell, Ok(status))
             }
 
             ShellCommand::External(cmd, args) => {
-                let (real_args, redirect) = split_redirect(&args);
-                if pathsearch::find_executable_in_path(cmd).is_none() {
-                    println!("{cmd}: command not found");
-                    return true;
-                }
-
-                let output = std::process::Command::new(cmd)
-                    .args(&real_args)
-                    .output()
-                    .unwrap();
+                let Some(path) = shell.commands.resolve(cmd) else {
+                    return command_not_found(cmd, redirects);
+                };
 
-                match &redirect {
-                    Some(Redirect::Stdout(file)) => {
-                        std::fs::write(file, &output.stdout).unwrap();
-                        eprint!("{}", String::from_utf8_lossy(&output.stderr));
-                    }
-                    Some(Redirect::Stderr(file)) => {
-                        std::fs::write(file, &output.stderr).unwrap();
-                        print!("{}", String::from_utf8_lossy(&output.stdout));
+                // stdio is inherited unless redirected, so programs that need
+                // the terminal (editors, pagers, REPLs) talk to it directly
+                let argv: Vec<&str> = std::iter::once(cmd).chain(args).collect();
+                let pgroup = shell.job_control.then_some(0);
+                let spawned = if redirects.is_empty() {
+                    spawn::spawn(&path, &argv, pgroup)
+                } else {
+                    let mut command = Command::new(path);
+                    command.arg0(cmd).args(&argv[1..]);
+                    if let Some(pgroup) = pgroup {
+                        command.process_group(pgroup);
                     }
-                    Some(Redirect::AppendStdout(file)) => {
-                        std::fs::OpenOptions::new()
-                            .write(true)
-                            .append(true)
-                            .create(true)
-                            .open(file)
-                            .unwrap()
-                            .write_all(&output.stdout)
-                            .unwrap();
-                        eprint!("{}", String::from_utf8_lossy(&output.stderr));
+                    if !redirect_command(&mut command, redirects, Streams::new()) {
+                        return 1;
                     }
-                    Some(Redirect::AppendStderr(file)) => {
-                        std::fs::OpenOptions::new()
-                            .write(true)
-                            .append(true)
-                            .create(true)
-                            .open(file)
-                            .unwrap()
-                            .write_all(&output.stderr)
-                            .unwrap();
-                        eprint!("{}", String::from_utf8_lossy(&output.stdout));
+                    // reaped by wait_foreground
+                    #[allow(clippy::zombie_processes)]
+                    command.spawn().map(|child| child.id() as i32)
+                };
+
+                match spawned {
+                    Ok(pid) => {
+                        if shell.job_control {
+                            terminal::give_terminal(pid);
+                        }
+                        let id = shell.jobs.add(&[pid], argv.join(" "));
+                        wait_foreground(shell, id).unwrap_or(128 + libc::SIGTSTP)
                     }
-                    None => {
-                        print!("{}", String::from_utf8_lossy(&output.stdout));
-                        eprint!("{}", String::from_utf8_lossy(&output.stderr));
+                    Err(e) => {
+                        eprintln!("{cmd}: {}", error_text(&e));
+                        126
                     }
                 }
             }
 
-            ShellCommand::Empty => {}
-        }
+            ShellCommand::Wait(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let status = built_in_commands::wait(shell, &args, &mut ctx);
+                ctx.finish(shell, Ok(status))
+            }
 
-        true
-    }
-}
+            ShellCommand::Fg(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let result = built_in_commands::fg(shell, &args, &mut ctx);
+                ctx.finish(shell, result)
+            }
 
-pub fn tokenize(input: &str) -> Vec<String> {
-    let mut args = Vec::new();
-    let mut current = String::new();
-    let mut in_single = false;
-    let mut in_double = false;
-    let mut in_blackslash = false;
+            ShellCommand::Bg(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let result = built_in_commands::bg(shell, &args, &mut ctx);
+                ctx.finish(shell, result)
+            }
 
-    for c in input.chars() {
-        if in_blackslash {
-            current.push(c);
-            in_blackslash = false;
-            continue;
-        }
+            ShellCommand::Disown(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let status = built_in_commands::disown(shell, &args, &mut ctx);
+                ctx.finish(shell, Ok(status))
+            }
 
-        match c {
-            '\'' if !in_double => {
-                in_single = !in_single;
+            ShellCommand::Kill(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let result = built_in_commands::kill(shell, &args, &mut ctx);
+                ctx.finish(shell, result)
             }
 
-            '"' if !in_single => {
-                in_double = !in_double;
+            ShellCommand::Unset(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let status = built_in_commands::unset(shell, &args, &mut ctx);
+                ctx.finish(shell, Ok(status))
+            }
+
+            ShellCommand::Unalias(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let status = built_in_commands::unalias(shell, &args, &mut ctx);
+                ctx.finish(shell, Ok(status))
+            }
+
+            ShellCommand::Let(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let status = built_in_commands::let_(shell, &args, &mut ctx);
+                ctx.finish(shell, Ok(status))
+            }
+
+            ShellCommand::Arith(expr) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let status = built_in_commands::arith(shell, expr, &mut ctx);
+                ctx.finish(shell, Ok(status))
+            }
+
+            ShellCommand::Break(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let status = built_in_commands::break_(shell, &args, &mut ctx);
+                ctx.finish(shell, Ok(status))
+            }
+
+            ShellCommand::Return(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let status = built_in_commands::return_(shell, &args, &mut ctx);
+                ctx.finish(shell, Ok(status))
+            }
+
+            ShellCommand::Shift(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let status = built_in_commands::shift(shell, &args, &mut ctx);
+                ctx.finish(shell, Ok(status))
+            }
+
+            ShellCommand::Continue(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let status = built_in_commands::continue_(shell, &args, &mut ctx);
+                ctx.finish(shell, Ok(status))
+            }
+
+            ShellCommand::Jobs(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let result = built_in_commands::jobs(shell, &args, &mut ctx);
+                ctx.finish(shell, result)
+            }
+
+            ShellCommand::Complete(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let result = built_in_commands::complete(shell, &args, &mut ctx);
+                ctx.finish(shell, result)
             }
 
-            '\\' if !in_single => {
-                in_blackslash = true;
+            ShellCommand::Compgen(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let result = built_in_commands::compgen(&args, &mut ctx);
+                ctx.finish(shell, result)
+            }
+
+            ShellCommand::Dotenv(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let status = built_in_commands::dotenv(shell, &args, &mut ctx);
+                ctx.finish(shell, Ok(status))
             }
 
-            ' ' | '\t' if !in_single && !in_double => {
-                if !current.is_empty() {
-                    args.push(current.clone());
-                    current.clear();
+            // exec with a command replaces the shell; without one its
+            // redirections apply to the shell itself from then on
+            ShellCommand::Exec(args) => {
+                let Some((cmd, rest)) = args.split_first() else {
+                    return if redirect_shell(redirects) { 0 } else { 1 };
+                };
+                let Some(path) = shell.commands.resolve(cmd) else {
+                    return command_not_found(cmd, redirects);
+                };
+
+                let mut command = Command::new(path);
+                command.arg0(cmd).args(rest);
+                if redirect_command(&mut command, redirects, Streams::new()) {
+                    if shell.job_control {
+                        terminal::release();
+                    }
+                    let e = command.exec();
+                    eprintln!("exec: {cmd}: {}", error_text(&e));
+                    return 126;
                 }
+                1
             }
 
-            _ => current.push(c),
-        }
-    }
+            ShellCommand::Export(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let result = built_in_commands::export(shell, &args, &mut ctx);
+                ctx.finish(shell, result)
+            }
 
-    if !current.is_empty() {
-        args.push(current);
-    }
+            ShellCommand::Local(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let result = built_in_commands::local(shell, &args, &mut ctx);
+                ctx.finish(shell, result)
+            }
 
-    args
-}
+            ShellCommand::Alias(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let result = built_in_commands::alias(shell, &args, &mut ctx);
+                ctx.finish(shell, result)
+            }
 
-fn split_redirect<'a>(args: &'a [&'a str]) -> (Vec<&'a str>, Option<Redirect<'a>>) {
-    if let Some(pos) = args.iter().position(|a| *a == ">>" || *a == "1>>") {
-        if pos + 1 < args.len() {
-            return (
-                args[..pos].to_vec(),
-                Some(Redirect::AppendStdout(&args[pos + 1])),
-            );
+            ShellCommand::Trap(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let result = built_in_commands::trap(shell, &args, &mut ctx);
+                ctx.finish(shell, result)
+            }
+
+            ShellCommand::Set(args) => {
+                let Some(mut ctx) = ExecutionContext::new(redirects, stdout) else {
+                    return 1;
+                };
+                let result = built_in_commands::set(shell, &args, &mut ctx);
+                ctx.finish(shell, result)
+            }
+
+            ShellCommand::Background(cmd, args) => {
+                let Some(path) = shell.commands.resolve(cmd) else {
+                    return command_not_found(cmd, redirects);
+                };
+
+                let mut command = Command::new(path);
+                command.arg0(cmd).args(&args);
+                if shell.job_control {
+                    command.process_group(0);
+                }
+                if !redirect_command(&mut command, redirects, Streams::new()) {
+                    return 1;
+                }
+
+                // reaped through the job table rather than the Child handle
+                #[allow(clippy::zombie_processes)]
+                let child = match command.spawn() {
+                    Ok(child) => child,
+                    Err(e) => {
+                        eprintln!("{cmd}: {}", error_text(&e));
+                        return 126;
+                    }
+                };
+                let pid = child.id() as i32;
+                let line = std::iter::once(cmd)
+                    .chain(args.iter().copied())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let id = shell.jobs.add(&[pid], line);
+                shell.last_background = Some(pid);
+                if shell.interactive {
+                    let _ = writeln!(io::stdout(), "[{id}] {pid}");
+                }
+                0
+            }
+
+            // a line of nothing but redirections still creates the files
+            ShellCommand::Empty => match apply_redirects(redirects, Streams::new()) {
+                Some(_) => 0,
+                None => 1,
+            },
         }
     }
-    if let Some(pos) = args.iter().position(|a| *a == "2>>") {
-        if pos + 1 < args.len() {
-            return (
-                args[..pos].to_vec(),
-                Some(Redirect::AppendStderr(&args[pos + 1])),
-            );
+}
+
+// Waits for a job running in the foreground and returns its status. If
+// Ctrl-Z stops it, it stays in the job table as stopped and None is
+// returned so the shell can go back to the prompt.
+fn wait_foreground(shell: &mut Shell, id: usize) -> Option<i32> {
+    wait_job(shell, id)?.status()
+}
+
+// Like wait_foreground, but returns the finished job itself, which has
+// the status of each of its processes.
+fn wait_job(shell: &mut Shell, id: usize) -> Option<Job> {
+    let pids: Vec<i32> = shell
+        .jobs
+        .get(id)?
+        .processes
+        .iter()
+        .filter(|p| p.status.is_none())
+        .map(|p| p.pid)
+        .collect();
+
+    for pid in pids {
+        match spawn::wait_foreground(pid) {
+            Ok(Some(status)) => shell.jobs.record_exit(pid, status),
+            Ok(None) => {
+                if shell.job_control {
+                    terminal::reclaim();
+                }
+                shell.jobs.set_stopped(id, true);
+                let job = shell.jobs.get(id)?;
+                println!();
+                println!(
+                    "[{id}]{}  {:<24}{}",
+                    shell.jobs.marker(id),
+                    "Stopped",
+                    job.command
+                );
+                return None;
+            }
+            Err(_) => shell.jobs.record_exit(pid, 127),
         }
     }
-    if let Some(pos) = args.iter().position(|a| *a == "2>") {
-        if pos + 1 < args.len() {
-            return (args[..pos].to_vec(), Some(Redirect::Stderr(&args[pos + 1])));
-        }
+    if shell.job_control {
+        terminal::reclaim();
     }
-    if let Some(pos) = args.iter().position(|a| *a == ">" || *a == "1>") {
-        if pos + 1 < args.len() {
-            return (args[..pos].to_vec(), Some(Redirect::Stdout(&args[pos + 1])));
-        }
+    let job = shell.jobs.remove(id)?;
+    // the ^C echoed by the terminal leaves the cursor mid-line
+    if job
+        .processes
+        .iter()
+        .any(|p| p.status == Some(128 + libc::SIGINT))
+    {
+        println!();
     }
-    (args.to_vec(), None)
+    Some(job)
+}
+
+// Commands joined by `|`, each one's stdout feeding the next one's stdin.
+// A single command with no `|` is just a pipeline of one.
+pub struct Pipeline<'a> {
+    stages: Vec<(Stage<'a>, Vec<Redirect<'a>>)>,
+    // the command line as shown in the job table
+    line: String,
+    background: bool,
+    // `! pipeline`: succeeds if the pipeline fails and vice versa
+    negate: bool,
+    // the words of each simple command in it, for `set -x` to show
+    traced: Vec<Vec<&'a str>>,
+}
+
+// One command of a pipeline: a simple command with any `NAME=value`
+// prefixes for its environment, a list in parentheses that runs in a
+// subshell, `(cd /tmp && ls)`, or one in braces that runs in the shell
+// itself, `{ cd /tmp; ls; }`. Or the definition of a function, `name() {
+// ...; }`, whose body is the group and its redirections.
+enum Stage<'a> {
+    Command(ShellCommand<'a>, Vec<(&'a str, &'a str)>),
+    Subshell(&'a [Token]),
+    Group(&'a [Token]),
+    // the whole `while ...; do ...; done`
+    Loop(&'a [Token]),
+    // the whole `[[ ... ]]`
+    Test(&'a [Token]),
+    Function(&'a str, &'a [Token]),
+}
+
+// How a stage that becomes a process is started.
+enum Launch<'a> {
+    Exec(&'a str, Command),
+    Fork(&'a [Token]),
+    Function(&'a str, Vec<&'a str>, Vec<(&'a str, &'a str)>),
+    Builtin(ShellCommand<'a>, Vec<(&'a str, &'a str)>),
 }
 
-fn write_output(text: &str, redirect: Option<Redirect>) {
-    match redirect {
-        Some(Redirect::Stdout(file)) => {
-            std::fs::write(file, text.as_bytes()).unwrap();
+impl<'a> Pipeline<'a> {
+    pub fn parse(tokens: &'a [Token]) -> Result<Self, ParseError<'a>> {
+        let all = tokens;
+        let (tokens, background) = match tokens {
+            [rest @ .., Token::Op(op)] if op == "&" => (rest, true),
+            _ => (tokens, false),
+        };
+        let (tokens, negate) = match tokens {
+            [Token::Word(bang), rest @ ..] if bang == "!" => (rest, true),
+            _ => (tokens, false),
+        };
+        if negate && tokens.is_empty() {
+            return Err(ParseError::UnexpectedNewline);
         }
-        Some(Redirect::Stderr(file)) => {
-            std::fs::write(file, text.as_bytes()).unwrap();
+        let line = tokens
+            .iter()
+            .map(Token::to_string)
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let mut stages = Vec::new();
+        let mut traced = Vec::new();
+        // where the `|` after the current stage is
+        let mut end = 0;
+        for stage in split_op(tokens, "|") {
+            end += stage.len();
+            if stage.is_empty() {
+                // nothing before a `&`, or before or after a `|`
+                let token = tokens.get(end).or(tokens.last()).or(all.last());
+                return Err(ParseError::at(token));
+            }
+            end += 1;
+            if let Some((name, body)) = function_definition(stage) {
+                // the body has to be a group
+                if body.is_empty() || nesting(body, 0) <= 0 {
+                    return Err(ParseError::at(body.first()));
+                }
+                parse_group(body)?;
+                stages.push((Stage::Function(name, body), Vec::new()));
+                continue;
+            }
+            if nesting(stage, 0) > 0 {
+                stages.push(parse_group(stage)?);
+                continue;
+            }
+            let (words, redirects) = parse_stage(stage)?;
+            traced.push(words.clone());
+            let (assignments, words) = split_assignments(&words);
+            let command = ShellCommand::parse(words);
+            stages.push((Stage::Command(command, assignments), redirects));
         }
-        Some(Redirect::AppendStdout(file)) => {
-            std::fs::OpenOptions::new()
-                .write(true)
-                .append(true)
-                .create(true)
-                .open(file)
-                .unwrap()
-                .write_all(text.as_bytes())
-                .unwrap();
+
+        Ok(Pipeline {
+            stages,
+            line,
+            background,
+            negate,
+            traced,
+        })
+    }
+
+    // Runs the pipeline and records its status as `$?`: the last stage's
+    // (under pipefail, the last one that failed), 0 for a background job,
+    // 128+SIGTSTP if Ctrl-Z stopped it. Each stage's is kept as well.
+    // Under `set -e` a failure ends the shell, unless the pipeline is
+    // negated or a condition.
+    pub fn execute(self, shell: &mut Shell) -> i32 {
+        let negate = self.negate;
+        if shell.options.xtrace {
+            self.trace(shell);
         }
-        Some(Redirect::AppendStderr(file)) => {
-            std::fs::OpenOptions::new()
-                .write(true)
-                .append(true)
-                .create(true)
-                .open(file)
-                .unwrap()
-                .write_all(text.as_bytes())
-                .unwrap();
+        let statuses = self.run(shell);
+        let status = if shell.options.pipefail {
+            statuses
+                .iter()
+                .rev()
+                .find(|&&s| s != 0)
+                .copied()
+                .unwrap_or(0)
+        } else {
+            statuses.last().copied().unwrap_or(1)
+        };
+        let status = match status {
+            0 if negate => 1,
+            _ if negate => 0,
+            status => status,
+        };
+        shell.status = status;
+        shell.pipestatus = statuses;
+        if status != 0 && shell.options.errexit && !negate && shell.condition_depth == 0 {
+            shell.exit_status = Some(status);
         }
-        None => {
-            println!("{text}");
+        status
+    }
+
+    // Under `set -x`, prints each simple command to stderr as it's about to
+    // run, its words expanded and quoted where they need it, after $PS4.
+    fn trace(&self, shell: &Shell) {
+        let ps4 = shell.vars.get("PS4").unwrap_or_else(|| "+ ".to_string());
+        let marked = format!("{}{}", expand::MARKER, expand::QUOTED);
+        for words in &self.traced {
+            let line: Vec<String> = match words.as_slice() {
+                // `(( expr ))` as it was written
+                [arith] if arith.starts_with("((") => vec![arith.replace(&marked, "$")],
+                _ => words.iter().map(|word| trace_quote(word)).collect(),
+            };
+            eprintln!("{ps4}{}", line.join(" "));
+        }
+    }
+
+    // Runs the stages and returns the status of each.
+    fn run(mut self, shell: &mut Shell) -> Vec<i32> {
+        let in_shell = match self.stages.as_slice() {
+            // a function in the background runs in a subshell, like a group
+            [(Stage::Command(ShellCommand::External(cmd, _), _), _)]
+                if self.background && shell.functions.contains_key(*cmd) =>
+            {
+                false
+            }
+            [(Stage::Command(..), _) | (Stage::Function(..), _)] => true,
+            // like anything else in the background or in a pipeline, a
+            // group there runs in a subshell
+            [(Stage::Group(_) | Stage::Loop(_) | Stage::Test(_), _)] => !self.background,
+            _ => false,
+        };
+        if in_shell {
+            self.guard_clobber(&shell.options);
+            let (stage, redirects) = self.stages.remove(0);
+            let status = match (stage, self.background) {
+                (Stage::Command(ShellCommand::Empty, assignments), _)
+                    if !assignments.is_empty() =>
+                {
+                    for (name, value) in assignments {
+                        shell.vars.set(name, value);
+                    }
+                    match ShellCommand::Empty.execute_to(shell, &redirects, Box::new(io::stdout()))
+                    {
+                        0 => shell.substitution_status.unwrap_or(0),
+                        status => status,
+                    }
+                }
+                (Stage::Command(ShellCommand::External(cmd, args), assignments), _)
+                    if shell.functions.contains_key(cmd) =>
+                {
+                    let saved = vars::push_prefixes(&assignments);
+                    let status = call_function(shell, cmd, args, &redirects);
+                    vars::restore(saved);
+                    status
+                }
+                (Stage::Command(command, assignments), background) => {
+                    let command = match command {
+                        ShellCommand::External(cmd, args) if background => {
+                            ShellCommand::Background(cmd, args)
+                        }
+                        command => command,
+                    };
+                    let saved = vars::push_prefixes(&assignments);
+                    let status = command.execute_to(shell, &redirects, Box::new(io::stdout()));
+                    vars::restore(saved);
+                    status
+                }
+                (Stage::Group(list), _) => run_group(shell, list, &redirects),
+                // run as a group of its own for the redirections to apply
+                // to all of it, coming back here without them
+                (Stage::Loop(tokens), _) if !redirects.is_empty() => {
+                    run_group(shell, tokens, &redirects)
+                }
+                (Stage::Loop(tokens), _) => run_loop(shell, tokens),
+                (Stage::Test(tokens), _) => conditional::run(shell, tokens),
+                (Stage::Function(name, body), _) => {
+                    if function_env::is_exported(name) {
+                        function_env::export(name, body);
+                    }
+                    shell.functions.insert(name.to_string(), body.to_vec());
+                    0
+                }
+                (Stage::Subshell(_), _) => unreachable!("subshells are always forked"),
+            };
+            return vec![status];
+        }
+
+        let line = std::mem::take(&mut self.line);
+        let background = self.background;
+        let spawned = self.spawn(shell, Streams::new(), !background);
+        let pids = spawned.pids();
+        let Some(&pid) = pids.last() else {
+            return spawned.statuses(None);
+        };
+
+        let id = shell.jobs.add(&pids, line);
+        if background {
+            shell.last_background = Some(pid);
+            // only someone at a prompt wants to know the job's number
+            if shell.interactive {
+                let _ = writeln!(io::stdout(), "[{id}] {pid}");
+            }
+            return vec![0];
+        }
+        match wait_job(shell, id) {
+            Some(job) => spawned.statuses(Some(&job)),
+            None => vec![128 + libc::SIGTSTP],
+        }
+    }
+
+    // Under noclobber a plain `>` may only create files.
+    fn guard_clobber(&mut self, options: &ShellOptions) {
+        if !options.noclobber {
+            return;
+        }
+        for (_, redirects) in &mut self.stages {
+            for redirect in redirects {
+                if let Redirect::File { mode, .. } = redirect
+                    && matches!(mode, OpenMode::Truncate)
+                {
+                    *mode = OpenMode::Create;
+                }
+            }
+        }
+    }
+
+    // Starts every stage without waiting for any of them and returns the
+    // pids of those that became processes. `ends` can supply the first
+    // stage's stdin (fd 0) and the last stage's stdout (fd 1). Under job
+    // control the stages share a process group, which a `foreground`
+    // pipeline is given the terminal for as soon as it exists.
+    pub(crate) fn spawn(
+        mut self,
+        shell: &mut Shell,
+        mut ends: Streams,
+        foreground: bool,
+    ) -> Spawned {
+        self.guard_clobber(&shell.options);
+        let last = self.stages.len() - 1;
+        let mut started = Vec::new();
+        let mut input: Option<PipeReader> = None;
+        let mut pgid = None;
+
+        for (i, (stage, redirects)) in self.stages.into_iter().enumerate() {
+            let launch = match stage {
+                Stage::Subshell(list)
+                | Stage::Group(list)
+                | Stage::Loop(list)
+                | Stage::Test(list) => Launch::Fork(list),
+                // defined in a subshell, which the definition goes away with
+                Stage::Function(..) => {
+                    input = None;
+                    started.push(Started::Finished(0));
+                    continue;
+                }
+                Stage::Command(ShellCommand::External(cmd, args), assignments)
+                    if shell.functions.contains_key(cmd) =>
+                {
+                    Launch::Function(cmd, args, assignments)
+                }
+                Stage::Command(ShellCommand::External(cmd, args), assignments) => {
+                    let Some(path) = shell.commands.resolve(cmd) else {
+                        input = None;
+                        started.push(Started::Finished(command_not_found(cmd, &redirects)));
+                        continue;
+                    };
+                    let mut command = Command::new(path);
+                    command.arg0(cmd).args(&args).envs(assignments);
+                    Launch::Exec(cmd, command)
+                }
+                Stage::Command(builtin, assignments) => Launch::Builtin(builtin, assignments),
+            };
+
+            let mut streams = Streams::new();
+            match input.take() {
+                Some(reader) => {
+                    streams.insert(0, reader.into());
+                }
+                None if i > 0 => {
+                    if let Ok(null) = File::open("/dev/null") {
+                        streams.insert(0, null.into());
+                    }
+                }
+                None => {
+                    if let Some(fd) = ends.remove(&0) {
+                        streams.insert(0, fd);
+                    }
+                }
+            }
+            if i < last {
+                let Some((reader, writer)) = pipe() else {
+                    break;
+                };
+                streams.insert(1, writer.into());
+                input = Some(reader);
+            } else if let Some(fd) = ends.remove(&1) {
+                streams.insert(1, fd);
+            }
+
+            // redirections are applied on top of the pipe, so an explicit
+            // one wins and `2>&1` sends stderr down the pipe too
+            let pgroup = shell.job_control.then_some(pgid.unwrap_or(0));
+            let (name, spawned) = match launch {
+                Launch::Exec(cmd, mut command) => {
+                    if let Some(pgroup) = pgroup {
+                        command.process_group(pgroup);
+                    }
+                    if !redirect_command(&mut command, &redirects, streams) {
+                        started.push(Started::Finished(1));
+                        continue;
+                    }
+                    // reaped by pid below or through the job table
+                    #[allow(clippy::zombie_processes)]
+                    let spawned = command.spawn().map(|child| child.id() as i32);
+                    (cmd, spawned)
+                }
+                Launch::Fork(list) => ("oxide", subshell(shell, list, &redirects, streams, pgroup)),
+                Launch::Function(name, args, assignments) => {
+                    // the subshell gets them by being forked while they're set
+                    let saved = vars::push_prefixes(&assignments);
+                    let spawned = fork_function(shell, name, args, &redirects, streams, pgroup);
+                    vars::restore(saved);
+                    (name, spawned)
+                }
+                Launch::Builtin(builtin, assignments) => {
+                    let saved = vars::push_prefixes(&assignments);
+                    let spawned = fork_builtin(shell, builtin, &redirects, streams, pgroup);
+                    vars::restore(saved);
+                    ("oxide", spawned)
+                }
+            };
+
+            match spawned {
+                Ok(pid) => {
+                    if shell.job_control && pgid.is_none() {
+                        pgid = Some(pid);
+                        if foreground {
+                            terminal::give_terminal(pid);
+                        }
+                    }
+                    started.push(Started::Process(pid));
+                }
+                Err(e) => {
+                    eprintln!("{name}: {}", error_text(&e));
+                    started.push(Started::Finished(126));
+                }
+            }
+        }
+        Spawned { stages: started }
+    }
+}
+
+// Runs a `{ ... }` group in the shell itself, with its redirections in
+// place for the whole group and the shell's own descriptors put back
+// afterwards.
+fn run_group(shell: &mut Shell, list: &[Token], redirects: &[Redirect]) -> i32 {
+    let Some(streams) = apply_redirects(redirects, Streams::new()) else {
+        return 1;
+    };
+    let _ = io::stdout().flush();
+    // -1 for an fd that wasn't open, which is closed again instead
+    let saved: Vec<(i32, i32)> = streams
+        .keys()
+        .map(|&fd| {
+            let fd = fd as i32;
+            (fd, unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) })
+        })
+        .collect();
+
+    let status = match install_streams(&streams) {
+        Ok(()) => {
+            drop(streams);
+            match CommandList::parse(list) {
+                Ok(list) => list.execute(shell),
+                Err(e) => {
+                    eprintln!("oxide: {e}");
+                    2
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("oxide: {}", error_text(&e));
+            1
+        }
+    };
+
+    let _ = io::stdout().flush();
+    for (fd, copy) in saved {
+        unsafe {
+            if copy < 0 {
+                libc::close(fd);
+            } else {
+                libc::dup2(copy, fd);
+                libc::close(copy);
+            }
+        }
+    }
+    status
+}
+
+// Runs `while condition; do body; done` in the shell: the body runs for as
+// long as the condition succeeds, or with `until`, fails. The status is the
+// body's last, or 0 if it never ran. Ctrl-C, whether it interrupted a
+// command or the shell itself, ends the loop, as does `break`.
+fn run_loop(shell: &mut Shell, tokens: &[Token]) -> i32 {
+    let until = matches!(&tokens[0], Token::Word(w) if w == "until");
+    let Some((condition, body)) = split_loop(&tokens[1..tokens.len() - 1]) else {
+        unreachable!("checked by parse_group");
+    };
+    let (Ok(condition), Ok(body)) = (CommandList::parse(condition), CommandList::parse(body))
+    else {
+        unreachable!("checked by parse_group");
+    };
+
+    signals::take_interrupt();
+    let interrupted = |status: i32| signals::take_interrupt() || status == 128 + libc::SIGINT;
+    shell.loop_depth += 1;
+    let mut status = 0;
+    loop {
+        shell.condition_depth += 1;
+        let tested = condition.execute(shell);
+        shell.condition_depth -= 1;
+        if shell.exit_status.is_some() || shell.returning || interrupted(tested) {
+            status = tested;
+            break;
+        }
+        match take_loop_control(shell) {
+            Some(true) => break,
+            Some(false) => continue,
+            None => {}
+        }
+        if (tested == 0) == until {
+            break;
+        }
+        status = body.execute(shell);
+        if shell.exit_status.is_some() || shell.returning || interrupted(status) {
+            break;
+        }
+        if take_loop_control(shell) == Some(true) {
+            break;
+        }
+    }
+    shell.loop_depth -= 1;
+    status
+}
+
+// What a `break` or `continue` that stopped a loop's commands means for
+// the loop: true to end it, false to go on with its next round. One meant
+// for an outer loop ends this one and is passed on, a level less.
+fn take_loop_control(shell: &mut Shell) -> Option<bool> {
+    let control = shell.loop_control.take()?;
+    Some(match control {
+        LoopControl::Break(1) => true,
+        LoopControl::Continue(1) => false,
+        LoopControl::Break(n) => {
+            shell.loop_control = Some(LoopControl::Break(n - 1));
+            true
+        }
+        LoopControl::Continue(n) => {
+            shell.loop_control = Some(LoopControl::Continue(n - 1));
+            true
+        }
+    })
+}
+
+// How deep functions can call each other, well short of where the shell
+// would run out of stack. $FUNCNEST can lower it, but not raise it.
+const FUNCTION_DEPTH: usize = 256;
+
+// Runs a function in the shell itself, with `args` as its positional
+// parameters for as long as it runs. `redirects` apply around the ones in
+// its definition.
+fn call_function(shell: &mut Shell, name: &str, args: Vec<&str>, redirects: &[Redirect]) -> i32 {
+    let limit = shell
+        .vars
+        .get("FUNCNEST")
+        .and_then(|n| n.parse().ok())
+        .filter(|&n| n > 0)
+        .map_or(FUNCTION_DEPTH, |n: usize| n.min(FUNCTION_DEPTH));
+    if shell.function_depth >= limit {
+        eprintln!("oxide: {name}: maximum function nesting level exceeded ({limit})");
+        return 1;
+    }
+    let body = shell.functions.get(name).cloned().unwrap_or_default();
+    let args = args.into_iter().map(String::from).collect();
+    let saved = std::mem::replace(&mut shell.positional, args);
+    shell.function_depth += 1;
+    shell.vars.push_scope();
+    let status = match parse_group(&body) {
+        Ok((Stage::Group(list), own)) => {
+            let mut all = redirects.to_vec();
+            all.extend(own);
+            run_group(shell, list, &all)
+        }
+        Ok((Stage::Subshell(list), own)) => {
+            let mut all = redirects.to_vec();
+            all.extend(own);
+            subshell(shell, list, &all, Streams::new(), None)
+                .and_then(spawn::wait)
+                .unwrap_or_else(|e| {
+                    eprintln!("oxide: {}", error_text(&e));
+                    126
+                })
+        }
+        _ => unreachable!("checked when it was defined"),
+    };
+    shell.vars.pop_scope();
+    shell.function_depth -= 1;
+    shell.returning = false;
+    shell.positional = saved;
+    status
+}
+
+// Starts a function in a subshell, as a stage of a pipeline or in the
+// background, returning its pid.
+fn fork_function(
+    shell: &mut Shell,
+    name: &str,
+    args: Vec<&str>,
+    redirects: &[Redirect],
+    streams: Streams,
+    pgroup: Option<i32>,
+) -> io::Result<i32> {
+    let body = shell.functions.get(name).cloned().unwrap_or_default();
+    let args = args.into_iter().map(String::from).collect();
+    let saved = std::mem::replace(&mut shell.positional, args);
+    shell.function_depth += 1;
+    shell.vars.push_scope();
+    let result = match parse_group(&body) {
+        Ok((Stage::Group(list) | Stage::Subshell(list), own)) => {
+            let mut all = redirects.to_vec();
+            all.extend(own);
+            subshell(shell, list, &all, streams, pgroup)
+        }
+        _ => unreachable!("checked when it was defined"),
+    };
+    shell.vars.pop_scope();
+    shell.function_depth -= 1;
+    shell.positional = saved;
+    result
+}
+
+// Starts a builtin in a subshell, as a stage of a pipeline, returning its
+// pid. Like any other stage it can't change the shell itself, so
+// `cd /tmp | cat` leaves the working directory as it was.
+fn fork_builtin(
+    shell: &mut Shell,
+    builtin: ShellCommand,
+    redirects: &[Redirect],
+    streams: Streams,
+    pgroup: Option<i32>,
+) -> io::Result<i32> {
+    let _ = io::stdout().flush();
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            if let Some(pgroup) = pgroup {
+                unsafe { libc::setpgid(0, pgroup) };
+            }
+            if let Err(e) = install_streams(&streams) {
+                eprintln!("oxide: {}", error_text(&e));
+                process::exit(1);
+            }
+            drop(streams);
+            close_exec_fds();
+            // `jobs | grep` still lists the shell's jobs
+            let jobs = std::mem::take(&mut shell.jobs);
+            shell.enter_subshell();
+            if matches!(builtin, ShellCommand::Jobs(_)) {
+                shell.jobs = jobs;
+            }
+            shell.status = builtin.execute_to(shell, redirects, Box::new(io::stdout()));
+            let _ = io::stdout().flush();
+            process::exit(shell.run_exit_trap());
+        }
+        pid => {
+            if let Some(pgroup) = pgroup {
+                unsafe { libc::setpgid(pid, if pgroup == 0 { pid } else { pgroup }) };
+            }
+            Ok(pid)
+        }
+    }
+}
+
+// Forks a subshell to run `list` with `streams`, then `redirects`, as its
+// descriptors. Whatever it changes in the shell (the working directory,
+// variables, traps) goes away with it.
+fn subshell(
+    shell: &mut Shell,
+    list: &[Token],
+    redirects: &[Redirect],
+    streams: Streams,
+    pgroup: Option<i32>,
+) -> io::Result<i32> {
+    // or the child would write out the parent's buffered output again
+    let _ = io::stdout().flush();
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            if let Some(pgroup) = pgroup {
+                unsafe { libc::setpgid(0, pgroup) };
+            }
+            let status = match apply_redirects(redirects, streams).map(|s| install_streams(&s)) {
+                Some(Ok(())) => {
+                    close_exec_fds();
+                    shell.enter_subshell();
+                    match CommandList::parse(list) {
+                        Ok(list) => {
+                            list.execute(shell);
+                            shell.run_exit_trap()
+                        }
+                        Err(e) => {
+                            eprintln!("oxide: {e}");
+                            2
+                        }
+                    }
+                }
+                Some(Err(e)) => {
+                    eprintln!("oxide: {}", error_text(&e));
+                    1
+                }
+                None => 1,
+            };
+            process::exit(status);
+        }
+        pid => {
+            // set on both sides of the fork, so it's in place whichever
+            // runs first
+            if let Some(pgroup) = pgroup {
+                unsafe { libc::setpgid(pid, if pgroup == 0 { pid } else { pgroup }) };
+            }
+            Ok(pid)
+        }
+    }
+}
+
+// Closes the descriptors an exec would have: the shell's own, which would
+// otherwise keep a forked subshell holding pipes open.
+fn close_exec_fds() {
+    let Ok(entries) = fs::read_dir("/dev/fd") else {
+        return;
+    };
+    let fds: Vec<i32> = entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    for fd in fds {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+        if flags >= 0 && flags & libc::FD_CLOEXEC != 0 {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+// The commands of a line, separated by `;` or `&` and run one after
+// another: `cd /tmp; ls; pwd`. One ended with `&` runs in the background
+// while the rest carry on.
+pub struct CommandList<'a> {
+    items: Vec<AndOr<'a>>,
+}
+
+impl<'a> CommandList<'a> {
+    pub fn parse(tokens: &'a [Token]) -> Result<Self, ParseError<'a>> {
+        let mut items = Vec::new();
+        let mut rest = tokens;
+
+        while !rest.is_empty() {
+            let Some(end) = find_op(rest, &[";", "&"]) else {
+                items.push(AndOr::parse(rest)?);
+                break;
+            };
+            if end == 0 {
+                return Err(ParseError::Unexpected(&rest[0]));
+            }
+            // the `&` stays with its command, which is what backgrounds it
+            let (item, tail) = if rest[end].is_op("&") {
+                rest.split_at(end + 1)
+            } else {
+                (&rest[..end], &rest[end + 1..])
+            };
+            items.push(AndOr::parse(item)?);
+            rest = tail;
+        }
+
+        Ok(CommandList { items })
+    }
+
+    pub fn execute(&self, shell: &mut Shell) -> i32 {
+        for item in &self.items {
+            item.execute(shell);
+            // a signal that arrived while it ran has its trap run before
+            // anything else does
+            shell.run_traps();
+            if shell.unwinding() {
+                break;
+            }
+        }
+        shell.status
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Connector {
+    And,
+    Or,
+}
+
+// Pipelines joined by `&&` and `||`. Each one after the first runs only if
+// the last one that ran succeeded (`&&`) or failed (`||`), so in
+// `make && ./run || echo failed` a failing make skips ./run and reports.
+pub struct AndOr<'a> {
+    pipelines: Vec<(Option<Connector>, &'a [Token])>,
+}
+
+impl<'a> AndOr<'a> {
+    pub fn parse(tokens: &'a [Token]) -> Result<Self, ParseError<'a>> {
+        let mut pipelines = Vec::new();
+        let mut connector = None;
+        let mut rest = tokens;
+
+        loop {
+            let end = find_op(rest, &["&&", "||"]).unwrap_or(rest.len());
+            let (pipeline, tail) = rest.split_at(end);
+            if pipeline.is_empty() {
+                return Err(ParseError::at(tail.first()));
+            }
+            // words are only expanded when their pipeline runs, so this is
+            // just to report syntax errors before anything has run
+            Pipeline::parse(pipeline)?;
+            pipelines.push((connector, pipeline));
+
+            let Some((op, tail)) = tail.split_first() else {
+                break;
+            };
+            connector = Some(if op.is_op("&&") {
+                Connector::And
+            } else {
+                Connector::Or
+            });
+            rest = tail;
+        }
+
+        Ok(AndOr { pipelines })
+    }
+
+    pub fn execute(&self, shell: &mut Shell) -> i32 {
+        for (i, &(connector, tokens)) in self.pipelines.iter().enumerate() {
+            let skip = match connector {
+                None => false,
+                Some(Connector::And) => shell.status != 0,
+                Some(Connector::Or) => shell.status == 0,
+            };
+            if skip {
+                continue;
+            }
+            let condition = i + 1 < self.pipelines.len();
+            shell.condition_depth += condition as usize;
+            run_pipeline(shell, tokens);
+            shell.condition_depth -= condition as usize;
+            if shell.unwinding() {
+                break;
+            }
+        }
+        shell.status
+    }
+}
+
+// Expands a pipeline's words, starts its process substitutions and runs it.
+fn run_pipeline(shell: &mut Shell, tokens: &[Token]) -> i32 {
+    shell.substitution_status = None;
+    let tokens = match expand::words(shell, tokens.to_vec()) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            eprintln!("oxide: {e}");
+            shell.status = 1;
+            // a script doesn't go on past a failed expansion, such as an
+            // unset variable under `set -u`; `set -e` stops anything else
+            if !shell.interactive || (shell.options.errexit && shell.condition_depth == 0) {
+                shell.exit_status = Some(1);
+            }
+            return 1;
+        }
+    };
+    let (tokens, _substitutions) = match process_sub::start(shell, tokens) {
+        Ok(started) => started,
+        Err(e) => {
+            eprintln!("oxide: {e}");
+            shell.status = 1;
+            return 1;
+        }
+    };
+    match Pipeline::parse(&tokens) {
+        Ok(pipeline) => pipeline.execute(shell),
+        Err(e) => {
+            eprintln!("oxide: {e}");
+            shell.status = 2;
+            2
+        }
+    }
+}
+
+// A word the way `set -x` shows it: in single quotes unless it's only
+// characters that never need them. Only the value of `NAME=value` is.
+fn trace_quote(word: &str) -> String {
+    if let Some((name, value)) = word.split_once('=')
+        && env_file::is_name(name)
+    {
+        return format!("{name}={}", trace_quote(value));
+    }
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%^".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+// What became of each stage of a pipeline when it was started: a process
+// that's now running, or the status of one that ran in the shell (a
+// builtin) or couldn't be started at all.
+pub(crate) enum Started {
+    Process(i32),
+    Finished(i32),
+}
+
+pub(crate) struct Spawned {
+    pub stages: Vec<Started>,
+}
+
+impl Spawned {
+    pub fn pids(&self) -> Vec<i32> {
+        self.stages
+            .iter()
+            .filter_map(|stage| match *stage {
+                Started::Process(pid) => Some(pid),
+                Started::Finished(_) => None,
+            })
+            .collect()
+    }
+
+    // Each stage's status, taking those of processes from their job once
+    // it has finished.
+    fn statuses(&self, job: Option<&Job>) -> Vec<i32> {
+        self.stages
+            .iter()
+            .map(|stage| match *stage {
+                Started::Finished(status) => status,
+                Started::Process(pid) => job
+                    .and_then(|job| job.processes.iter().find(|p| p.pid == pid))
+                    .and_then(|p| p.status)
+                    .unwrap_or(0),
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Word(String),
+    // an unquoted operator: `|`, `&`, `;`, `&&`, `||`, `(`, `)`, or a
+    // redirection such as `2>>` or `2>&1`
+    Op(String),
+    // process substitution: `<(command)` or `>(command)`
+    ProcessSub(char, String),
+}
+
+impl Token {
+    pub fn is_op(&self, op: &str) -> bool {
+        matches!(self, Token::Op(o) if o == op)
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(s) => {
+                let glob = format!("{}{}", expand::MARKER, expand::GLOB);
+                let quoted = format!("{}{}", expand::MARKER, expand::QUOTED);
+                let s = s.replace(&glob, "").replace(&quoted, "$");
+                f.write_str(&s.replace(expand::MARKER, "$"))
+            }
+            Token::Op(s) => f.write_str(s),
+            Token::ProcessSub(c, command) => write!(f, "{c}({command})"),
+        }
+    }
+}
+
+pub fn tokenize(input: &str) -> Vec<Token> {
+    lex(input).0.into_iter().map(|(token, _)| token).collect()
+}
+
+// Whether `input` stops partway through a command, so the next line should
+// be read as more of it: inside quotes, after a `\` escaping the newline,
+// after `|`, `&&` or `||`, or inside a `(` or `{` group or a function
+// definition.
+pub fn incomplete(input: &str) -> bool {
+    let (tokens, open) = lex(input);
+    if open.is_some() {
+        return true;
+    }
+    let tokens: Vec<Token> = tokens.into_iter().map(|(token, _)| token).collect();
+    let depth: isize = (0..tokens.len()).map(|i| nesting(&tokens, i)).sum();
+    depth > 0
+        || match tokens.as_slice() {
+            // `name()`, still to be followed by the function's body
+            [.., open, close] if open.is_op("(") && close.is_op(")") => true,
+            [.., last] => last.is_op("|") || last.is_op("&&") || last.is_op("||"),
+            [] => false,
+        }
+}
+
+// Tokenizes `input`, pairing each token with the range of bytes it came
+// from. The words brace expansion makes of one word share its range. Also
+// returns the quote (or `\` before the final newline) left open at the end.
+fn lex(input: &str) -> (Vec<(Token, Range<usize>)>, Option<char>) {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    // whether the current word had quoting, so `""` is still a word and
+    // `"2">` isn't a redirection of fd 2
+    let mut quoted = false;
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut in_blackslash = false;
+    let mut braces = Vec::new();
+    // how far into `input` the characters read so far go
+    let offset = Cell::new(0);
+    let mut chars = input
+        .chars()
+        .inspect(|c| offset.set(offset.get() + c.len_utf8()))
+        .peekable();
+    let mut word_start = 0;
+    let mut continued = false;
+
+    while let Some(c) = chars.next() {
+        // nothing past `c` has been peeked at yet
+        let at = offset.get() - c.len_utf8();
+        if current.is_empty() && !quoted && !in_blackslash {
+            word_start = at;
+        }
+        if in_blackslash {
+            in_blackslash = false;
+            // `\` then a newline joins two lines into one
+            if c == '\n' {
+                continued = chars.peek().is_none();
+                continue;
+            }
+            // in double quotes it only escapes what's special there
+            if in_double && !matches!(c, '$' | '`' | '"' | '\\') {
+                current.push('\\');
+            }
+            current.push(c);
+            quoted = true;
+            continue;
+        }
+
+        match c {
+            '\'' if !in_double => {
+                in_single = !in_single;
+                quoted = true;
+            }
+
+            '"' if !in_single => {
+                in_double = !in_double;
+                quoted = true;
+            }
+
+            '\\' if !in_single => in_blackslash = true,
+
+            // a comment runs to the end of the line, which still ends the
+            // command
+            '#' if !in_single && !in_double && current.is_empty() && !quoted => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+
+            // `$~` is nothing to expand, and a marked `~` is a tilde prefix
+            '$' if !in_single && chars.peek() == Some(&'~') => current.push(c),
+
+            '~' if !in_single && !in_double && !quoted && tilde_prefix_starts(&current) => {
+                current.push(expand::MARKER);
+                current.push(c);
+            }
+
+            // the `$` of `${$}` is its name
+            '$' if !in_single
+                && current
+                    .strip_suffix('{')
+                    .is_some_and(|s| s.ends_with([expand::MARKER, expand::QUOTED])) =>
+            {
+                current.push(c)
+            }
+
+            // a command substitution is kept whole for expand to run
+            '$' if !in_single => {
+                current.push(expand::MARKER);
+                if in_double {
+                    current.push(expand::QUOTED);
+                }
+                if chars.next_if_eq(&'(').is_some() {
+                    current.push('(');
+                    current.push_str(&read_subcommand(&mut chars));
+                    current.push(')');
+                } else if chars.next_if_eq(&'$').is_some() {
+                    // `$$`, rather than a `$` and another expansion
+                    current.push('$');
+                }
+            }
+
+            // `$*` and `$?` are parameters, not patterns
+            '*' | '?' | '[' if !in_single && !in_double && !current.ends_with(expand::MARKER) => {
+                current.push(expand::MARKER);
+                current.push(expand::GLOB);
+                current.push(c);
+            }
+
+            // unquoted, these can make one word several; the braces of a
+            // `${name}` are left alone
+            '{' | ',' | '}' if !in_single && !in_double => {
+                let param = format!("{}{{", expand::MARKER);
+                let in_param = current
+                    .rfind(&param)
+                    .is_some_and(|i| !current[i..].contains('}'));
+                if !(c == '{' && current.ends_with(expand::MARKER) || c == '}' && in_param) {
+                    braces.push(current.len());
+                }
+                current.push(c);
+            }
+
+            // `cmd` is the old spelling of $(cmd)
+            '`' if !in_single => {
+                current.push(expand::MARKER);
+                if in_double {
+                    current.push(expand::QUOTED);
+                }
+                current.push('(');
+                current.push_str(&read_backquoted(&mut chars, in_double));
+                current.push(')');
+            }
+
+            // `(( expr ))` where a command starts is one word, for
+            // ShellCommand to recognize; its `$` expand without splitting.
+            // A `((` that isn't closed by `))` opens two subshells.
+            '(' if current.is_empty()
+                && !quoted
+                && !in_single
+                && !in_double
+                && chars.peek() == Some(&'(')
+                && awaits_command(&tokens)
+                && read_arithmetic(&mut input[offset.get()..].chars().peekable()).is_some() =>
+            {
+                chars.next();
+                let expr = read_arithmetic(&mut chars).unwrap_or_default();
+                let marked = format!("{}{}", expand::MARKER, expand::QUOTED);
+                let word = format!("(({}))", expr.replace('$', &marked));
+                tokens.push((Token::Word(word), at..offset.get()));
+            }
+
+            ';' | '(' | ')' if !in_single && !in_double => {
+                if !current.is_empty() || quoted {
+                    push_word(
+                        &mut tokens,
+                        std::mem::take(&mut current),
+                        &mut braces,
+                        word_start..at,
+                    );
+                }
+                quoted = false;
+                tokens.push((Token::Op(c.to_string()), at..at + 1));
+            }
+
+            ' ' | '\t' | '\n' if !in_single && !in_double => {
+                if !current.is_empty() || quoted {
+                    push_word(
+                        &mut tokens,
+                        std::mem::take(&mut current),
+                        &mut braces,
+                        word_start..at,
+                    );
+                }
+                quoted = false;
+                // a newline ends a command, unless the command isn't done
+                if c == '\n' && !awaits_command(&tokens) {
+                    tokens.push((Token::Op(";".to_string()), at..at + 1));
+                }
+            }
+
+            '<' | '>' if !in_single && !in_double && current.is_empty() && !quoted => {
+                if chars.next_if_eq(&'(').is_some() {
+                    let command = read_subcommand(&mut chars);
+                    tokens.push((Token::ProcessSub(c, command), at..offset.get()));
+                    continue;
+                }
+                let mut op = c.to_string();
+                read_operator(&mut op, c, &mut chars);
+                let span = at..at + op.len();
+                tokens.push((Token::Op(op), span));
+            }
+
+            '|' | '&' | '<' | '>' if !in_single && !in_double => {
+                // a lone digit right before `<` or `>` is the fd it redirects
+                let fd_prefix = matches!(c, '<' | '>')
+                    && !quoted
+                    && current.len() == 1
+                    && current.bytes().all(|b| b.is_ascii_digit());
+                let mut op = if fd_prefix {
+                    std::mem::take(&mut current)
+                } else {
+                    if !current.is_empty() || quoted {
+                        push_word(
+                            &mut tokens,
+                            std::mem::take(&mut current),
+                            &mut braces,
+                            word_start..at,
+                        );
+                    }
+                    String::new()
+                };
+                quoted = false;
+                let start = if fd_prefix { word_start } else { at };
+                op.push(c);
+                read_operator(&mut op, c, &mut chars);
+                let span = start..start + op.len();
+                tokens.push((Token::Op(op), span));
+            }
+
+            _ => current.push(c),
+        }
+    }
+
+    if !current.is_empty() || quoted {
+        push_word(&mut tokens, current, &mut braces, word_start..input.len());
+    }
+
+    let open = if in_single {
+        Some('\'')
+    } else if in_double {
+        Some('"')
+    } else if in_blackslash || continued {
+        Some('\\')
+    } else {
+        None
+    };
+    (tokens, open)
+}
+
+// Whether the tokens so far leave off where a command has to follow, so a
+// newline there doesn't end anything: at the start, or after an operator
+// such as `|` or `&&`, or a group's `(` or `{`.
+fn awaits_command(tokens: &[(Token, Range<usize>)]) -> bool {
+    match tokens.split_last() {
+        None => true,
+        // `name()` is followed by the function's body
+        Some(((Token::Op(op), _), [.., (open, _)])) if op == ")" && open.is_op("(") => true,
+        Some(((Token::Op(op), _), _)) => {
+            matches!(op.as_str(), ";" | "&" | "&&" | "||" | "|" | "(")
+        }
+        Some(((Token::Word(w), _), rest)) => {
+            matches!(w.as_str(), "{" | "while" | "until" | "do") && awaits_command(rest)
+        }
+        Some(((Token::ProcessSub(..), _), _)) => false,
+    }
+}
+
+// Ends a word, which brace expansion can turn into several. `braces` are
+// where its unquoted braces and commas are.
+fn push_word(
+    tokens: &mut Vec<(Token, Range<usize>)>,
+    word: String,
+    braces: &mut Vec<usize>,
+    span: Range<usize>,
+) {
+    if braces.is_empty() {
+        tokens.push((Token::Word(word), span));
+        return;
+    }
+    let words = brace::expand(&word, braces);
+    braces.clear();
+    tokens.extend(
+        words
+            .into_iter()
+            .filter(|w| !w.is_empty())
+            .map(|w| (Token::Word(w), span.clone())),
+    );
+}
+
+// Reads the command of a process substitution up to its closing `)`,
+// allowing nested parentheses and parentheses inside quotes.
+fn read_subcommand(chars: &mut Peekable<impl Iterator<Item = char>>) -> String {
+    let mut command = String::new();
+    let mut depth = 0;
+    let mut quote = None;
+
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', Some('\'')) => {}
+            ('\\', _) => {
+                command.push(c);
+                if let Some(next) = chars.next() {
+                    command.push(next);
+                }
+                continue;
+            }
+            (q, None) if q == '\'' || q == '"' => quote = Some(q),
+            (q, Some(open)) if q == open => quote = None,
+            ('(', None) => depth += 1,
+            (')', None) if depth == 0 => break,
+            (')', None) => depth -= 1,
+            _ => {}
+        }
+        command.push(c);
+    }
+    command
+}
+
+// Whether a `~` after `word` would start a tilde prefix: at the start of a
+// word, or after the `=` or a `:` of an assignment, as in PATH=~/bin:~/.local/bin.
+fn tilde_prefix_starts(word: &str) -> bool {
+    word.is_empty()
+        || (word.ends_with(['=', ':'])
+            && word
+                .split_once('=')
+                .is_some_and(|(name, _)| env_file::is_name(name)))
+}
+
+// Reads a `...` command substitution up to its closing backquote. Inside
+// it a backslash only escapes `$`, `` ` `` and `\` (and `"` within double
+// quotes); anywhere else it's kept for the command itself.
+fn read_backquoted(chars: &mut Peekable<impl Iterator<Item = char>>, in_double: bool) -> String {
+    let mut command = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '`' => break,
+            '\\' => {
+                match chars.next_if(|&n| matches!(n, '$' | '`' | '\\') || (in_double && n == '"')) {
+                    Some(next) => command.push(next),
+                    None => command.push(c),
+                }
+            }
+            _ => command.push(c),
+        }
+    }
+    command
+}
+
+// Reads the rest of a `(( expr ))` after its `((`, returning the
+// expression, or None if a `)` it didn't open isn't followed by another.
+fn read_arithmetic(chars: &mut Peekable<impl Iterator<Item = char>>) -> Option<String> {
+    let mut expr = String::new();
+    let mut depth = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return chars.next_if_eq(&')').map(|_| expr),
+            ')' => depth -= 1,
+            _ => {}
+        }
+        expr.push(c);
+    }
+    None
+}
+
+// Extends an operator that began with `first` as far as it goes: `&&`,
+// `||`, `>>`, `>|`, `>&2`, `<<`, `<<-`, `<<<`, `<&0`, `&>` and `&>>`.
+fn read_operator(op: &mut String, first: char, chars: &mut Peekable<impl Iterator<Item = char>>) {
+    let mut take = |c: char| {
+        let found = chars.next_if_eq(&c).is_some();
+        if found {
+            op.push(c);
+        }
+        found
+    };
+
+    let dup = match first {
+        '&' => {
+            if !take('&') && take('>') {
+                take('>');
+            }
+            false
+        }
+        '|' => {
+            take('|');
+            false
+        }
+        '>' => !take('>') && !take('|') && take('&'),
+        '<' => {
+            if take('<') {
+                let _ = take('<') || take('-');
+                false
+            } else {
+                take('&')
+            }
+        }
+        _ => false,
+    };
+
+    if dup {
+        while let Some(d) = chars.next_if(|c| c.is_ascii_digit()) {
+            op.push(d);
+        }
+    }
+}
+
+// The first of `ops` outside any group, since a `;` or `|` inside one
+// belongs to it.
+fn find_op(tokens: &[Token], ops: &[&str]) -> Option<usize> {
+    let mut depth = 0isize;
+    for (i, token) in tokens.iter().enumerate() {
+        depth = (depth + nesting(tokens, i)).max(0);
+        if depth == 0
+            && let Token::Op(op) = token
+            && ops.contains(&op.as_str())
+        {
+            return Some(i);
+        }
+    }
+    None
+}
+
+// How the token at `i` changes how deeply groups are nested: 1 if it opens
+// a `(` or `{` group, a loop or a `[[`, -1 if it closes one. Braces,
+// `while`, `until`, `done` and `[[` are reserved words, so they only count
+// where a command could start; `]]` counts where it ends a `[[`.
+fn nesting(tokens: &[Token], i: usize) -> isize {
+    match &tokens[i] {
+        Token::Op(op) if op == "(" => 1,
+        Token::Op(op) if op == ")" => -1,
+        Token::Word(w)
+            if matches!(w.as_str(), "{" | "while" | "until" | OPEN_TEST)
+                && command_starts(tokens, i) =>
+        {
+            1
+        }
+        Token::Word(w) if w == "]]" && closes_conditional(tokens, i) => -1,
+        Token::Word(w) if matches!(w.as_str(), "}" | "done") && command_starts(tokens, i) => -1,
+        _ => 0,
+    }
+}
+
+// `[[` as the tokenizer leaves it unquoted, each bracket marked as a
+// pattern character (MARKER, GLOB). Quoted, it isn't a reserved word.
+const OPEN_TEST: &str = "\0\u{1}[\0\u{1}[";
+
+// Whether the `]]` at `i` ends a `[[`: the last `[[` or `]]` before it is
+// a `[[` that started a command.
+fn closes_conditional(tokens: &[Token], i: usize) -> bool {
+    tokens[..i]
+        .iter()
+        .enumerate()
+        .rev()
+        .find_map(|(j, token)| match token {
+            Token::Word(w) if w == "]]" => Some(false),
+            Token::Word(w) if w == OPEN_TEST && command_starts(tokens, j) => Some(true),
+            _ => None,
+        })
+        .unwrap_or(false)
+}
+
+fn command_starts(tokens: &[Token], i: usize) -> bool {
+    let Some(prev) = i.checked_sub(1) else {
+        return true;
+    };
+    match &tokens[prev] {
+        // the body of `name() ...`
+        Token::Op(op) if op == ")" => prev.checked_sub(1).is_some_and(|i| tokens[i].is_op("(")),
+        Token::Op(op) => matches!(op.as_str(), ";" | "&" | "&&" | "||" | "|" | "("),
+        Token::Word(w) => {
+            matches!(w.as_str(), "{" | "!" | "while" | "until" | "do")
+                && command_starts(tokens, prev)
+                // the body of `function name ...`
+                || prev.checked_sub(1).is_some_and(|i| {
+                    matches!(&tokens[i], Token::Word(k) if k == "function")
+                        && command_starts(tokens, i)
+                })
+        }
+        Token::ProcessSub(..) => false,
+    }
+}
+
+fn split_op<'t>(mut tokens: &'t [Token], op: &str) -> Vec<&'t [Token]> {
+    let mut parts = Vec::new();
+    while let Some(i) = find_op(tokens, &[op]) {
+        parts.push(&tokens[..i]);
+        tokens = &tokens[i + 1..];
+    }
+    parts.push(tokens);
+    parts
+}
+
+// `( list )`, `{ list; }`, a `while`/`until` loop or `[[ ... ]]`, followed
+// by nothing but redirections, which apply to the whole group.
+fn parse_group(tokens: &[Token]) -> Result<(Stage<'_>, Vec<Redirect<'_>>), ParseError<'_>> {
+    let mut depth = 0;
+    let close = (0..tokens.len())
+        .position(|i| {
+            depth += nesting(tokens, i);
+            depth == 0
+        })
+        .ok_or(ParseError::UnexpectedEof)?;
+
+    let list = &tokens[1..close];
+    let closer = match &tokens[0] {
+        Token::Op(_) => ")",
+        Token::Word(w) if w == "{" => "}",
+        Token::Word(w) if w == OPEN_TEST => "]]",
+        _ => "done",
+    };
+    let closed = matches!(&tokens[close], Token::Op(t) | Token::Word(t) if t == closer);
+    if list.is_empty() || !closed {
+        return Err(ParseError::Unexpected(&tokens[close]));
+    }
+    let stage = if tokens[0].is_op("(") {
+        CommandList::parse(list)?;
+        Stage::Subshell(list)
+    } else if closer == "}" {
+        CommandList::parse(list)?;
+        Stage::Group(list)
+    } else if closer == "]]" {
+        conditional::parse(&tokens[..=close])?;
+        Stage::Test(&tokens[..=close])
+    } else {
+        let (condition, body) = split_loop(list).ok_or(ParseError::Unexpected(&tokens[close]))?;
+        match condition.last() {
+            None => return Err(ParseError::Unexpected(&list[0])),
+            Some(_) if body.is_empty() => return Err(ParseError::Unexpected(&tokens[close])),
+            _ => {}
+        }
+        CommandList::parse(condition)?;
+        CommandList::parse(body)?;
+        Stage::Loop(&tokens[..=close])
+    };
+    let (words, redirects) = parse_stage(&tokens[close + 1..])?;
+    if let Some(&word) = words.first() {
+        // the token the word came from, rather than one that reads the same
+        let token = tokens
+            .iter()
+            .find(|t| matches!(t, Token::Word(w) if std::ptr::eq(w.as_str(), word)));
+        return Err(ParseError::at(token));
+    }
+    Ok((stage, redirects))
+}
+
+// Splits what's between `while` and `done` at its `do`, into the condition
+// and the body.
+fn split_loop(list: &[Token]) -> Option<(&[Token], &[Token])> {
+    let mut depth = 0;
+    let i = (0..list.len()).position(|i| {
+        depth += nesting(list, i);
+        depth == 0 && matches!(&list[i], Token::Word(w) if w == "do") && command_starts(list, i)
+    })?;
+    Some((&list[..i], &list[i + 1..]))
+}
+
+// `name() group` or `function name [()] group`: the name, and the group
+// with any redirections after it. Only the shape is checked here.
+fn function_definition(tokens: &[Token]) -> Option<(&str, &[Token])> {
+    let parens =
+        |rest: &[Token]| matches!(rest, [open, close, ..] if open.is_op("(") && close.is_op(")"));
+    match tokens {
+        [Token::Word(keyword), Token::Word(name), rest @ ..] if keyword == "function" => {
+            Some((name, if parens(rest) { &rest[2..] } else { rest }))
+        }
+        [Token::Word(name), rest @ ..] if parens(rest) => Some((name, &rest[2..])),
+        _ => None,
+    }
+}
+
+// Splits the `NAME=value` words at the start of a command off the rest.
+// If nothing follows them, they set shell variables instead.
+fn split_assignments<'a, 'w>(words: &'w [&'a str]) -> (Vec<(&'a str, &'a str)>, &'w [&'a str]) {
+    let assignments: Vec<(&str, &str)> = words
+        .iter()
+        .map_while(|word| {
+            word.split_once('=')
+                .filter(|(name, _)| env_file::is_name(name))
+        })
+        .collect();
+    let rest = &words[assignments.len()..];
+    (assignments, rest)
+}
+
+// Separates one command of a pipeline into its words and redirections.
+fn parse_stage(tokens: &[Token]) -> Result<(Vec<&str>, Vec<Redirect<'_>>), ParseError<'_>> {
+    let mut words = Vec::new();
+    let mut redirects = Vec::new();
+    let mut tokens = tokens.iter();
+
+    while let Some(token) = tokens.next() {
+        let op = match token {
+            Token::Word(word) => {
+                words.push(word.as_str());
+                continue;
+            }
+            // a word once it's started, which happens after the line has
+            // been checked
+            Token::ProcessSub(_, command) => {
+                words.push(command.as_str());
+                continue;
+            }
+            Token::Op(op) => op,
+        };
+        let Some(operator) = parse_operator(op) else {
+            return Err(ParseError::Unexpected(token));
+        };
+        if let Operator::Dup(fd, target) = operator {
+            redirects.push(Redirect::Dup { fd, target });
+            continue;
         }
+
+        let target = match tokens.next() {
+            Some(Token::Word(word) | Token::ProcessSub(_, word)) => word.as_str(),
+            other => return Err(ParseError::at(other)),
+        };
+        match operator {
+            Operator::Open(fd, mode) => redirects.push(Redirect::File {
+                fd,
+                path: target,
+                mode,
+            }),
+            // &> file is shorthand for > file 2>&1
+            Operator::OpenBoth(mode) => {
+                redirects.push(Redirect::File {
+                    fd: 1,
+                    path: target,
+                    mode,
+                });
+                redirects.push(Redirect::Dup { fd: 2, target: 1 });
+            }
+            Operator::Text(fd) => redirects.push(Redirect::Text { fd, text: target }),
+            Operator::Word(fd) => redirects.push(Redirect::Word { fd, word: target }),
+            Operator::Dup(..) => unreachable!(),
+        }
+    }
+
+    Ok((words, redirects))
+}
+
+enum Operator {
+    Open(usize, OpenMode),
+    OpenBoth(OpenMode),
+    Dup(usize, usize),
+    Text(usize),
+    Word(usize),
+}
+
+// Recognizes `>`, `>>`, `>|`, `<`, `<<`, `<<<`, `>&m` and `<&m`, each
+// optionally preceded by the fd it applies to, plus `&>` and `&>>`.
+fn parse_operator(token: &str) -> Option<Operator> {
+    match token {
+        "&>" => return Some(Operator::OpenBoth(OpenMode::Truncate)),
+        "&>>" => return Some(Operator::OpenBoth(OpenMode::Append)),
+        _ => {}
+    }
+    let split = token.find(|c: char| !c.is_ascii_digit())?;
+    let (fd, op) = token.split_at(split);
+    let fd = match fd {
+        "" if op.starts_with('<') => 0,
+        "" => 1,
+        _ => fd_number(fd)?,
+    };
+
+    match op {
+        ">" => Some(Operator::Open(fd, OpenMode::Truncate)),
+        ">|" => Some(Operator::Open(fd, OpenMode::Clobber)),
+        ">>" => Some(Operator::Open(fd, OpenMode::Append)),
+        "<" => Some(Operator::Open(fd, OpenMode::Read)),
+        "<<" => Some(Operator::Text(fd)),
+        "<<<" => Some(Operator::Word(fd)),
+        _ => {
+            let target = op.strip_prefix(">&").or_else(|| op.strip_prefix("<&"))?;
+            Some(Operator::Dup(fd, fd_number(target)?))
+        }
+    }
+}
+
+// Redirections can name fds 0 through 9, as in POSIX.
+fn fd_number(text: &str) -> Option<usize> {
+    text.parse().ok().filter(|&fd| fd <= 9)
+}
+
+// Reports a command that couldn't be found on the stderr its redirections
+// give it, creating any files they name as they would have been, and
+// returns its status.
+fn command_not_found(cmd: &str, redirects: &[Redirect]) -> i32 {
+    let Some(mut ctx) = ExecutionContext::new(redirects, Box::new(io::sink())) else {
+        return 1;
+    };
+    if env::var_os("PATH").is_none() {
+        ctx.error(&format!("{cmd}: command not found (PATH is not set)"));
+    } else {
+        ctx.error(&format!("{cmd}: command not found"));
+    }
+    127
+}
+
+// Opens the file a redirection names, reporting failures the way the
+// shell reports them (`oxide: /no/such/dir/f: No such file or directory`).
+fn open_redirect(path: &str, mode: OpenMode) -> Option<File> {
+    let result = match mode {
+        OpenMode::Read => File::open(path),
+        OpenMode::Truncate | OpenMode::Clobber => File::create(path),
+        // devices such as /dev/null can still be written to
+        OpenMode::Create => match OpenOptions::new().write(true).create_new(true).open(path) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && !Path::new(path).is_file() => {
+                File::create(path)
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                eprintln!("oxide: {path}: cannot overwrite existing file");
+                return None;
+            }
+            result => result,
+        },
+        OpenMode::Append => OpenOptions::new().append(true).create(true).open(path),
+    };
+
+    match result {
+        Ok(f) => Some(f),
+        Err(e) => {
+            eprintln!("oxide: {path}: {}", error_text(&e));
+            None
+        }
+    }
+}
+
+// Applies redirections in order on top of `streams`. None when one of them
+// failed; the error has already been reported.
+fn apply_redirects(redirects: &[Redirect], mut streams: Streams) -> Option<Streams> {
+    for redirect in redirects {
+        match *redirect {
+            Redirect::File { fd, path, mode } => {
+                streams.insert(fd, open_redirect(path, mode)?.into());
+            }
+            Redirect::Dup { fd, target } if fd != target => {
+                let copy = match streams.get(&target) {
+                    Some(stream) => stream.try_clone(),
+                    None => shell_fd(target),
+                };
+                match copy {
+                    Ok(copy) => {
+                        streams.insert(fd, copy);
+                    }
+                    Err(e) => {
+                        eprintln!("oxide: {target}: {}", error_text(&e));
+                        return None;
+                    }
+                }
+            }
+            Redirect::Dup { .. } => {}
+            Redirect::Text { fd, text } => {
+                streams.insert(fd, text_file(text)?.into());
+            }
+            Redirect::Word { fd, word } => {
+                streams.insert(fd, text_file(&format!("{word}\n"))?.into());
+            }
+        }
+    }
+    Some(streams)
+}
+
+// An unlinked temporary file holding `text`, positioned at the start. A
+// file rather than a pipe so a long body can't fill the pipe before the
+// command reading it has started.
+fn text_file(text: &str) -> Option<File> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let path = env::temp_dir().join(format!("oxide-here-{}-{n}", process::id()));
+
+    let result = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .and_then(|mut file| {
+            fs::remove_file(&path)?;
+            file.write_all(text.as_bytes())?;
+            file.seek(SeekFrom::Start(0))?;
+            Ok(file)
+        });
+
+    match result {
+        Ok(file) => Some(file),
+        Err(e) => {
+            eprintln!(
+                "oxide: cannot create temp file for here-document: {}",
+                error_text(&e)
+            );
+            None
+        }
+    }
+}
+
+// Makes redirections permanent for the shell (`exec > log 2>&1`) by
+// installing them over its own descriptors, which every later command
+// inherits. Returns false if one of them failed.
+fn redirect_shell(redirects: &[Redirect]) -> bool {
+    let Some(streams) = apply_redirects(redirects, Streams::new()) else {
+        return false;
+    };
+    // anything already buffered belongs to the old stdout
+    let _ = io::stdout().flush();
+
+    for (fd, stream) in streams {
+        // the file may have been opened on the very fd it's meant for, in
+        // which case it only has to be kept open and inheritable
+        if stream.as_raw_fd() == fd as i32 {
+            unsafe { libc::fcntl(fd as i32, libc::F_SETFD, 0) };
+            let _ = stream.into_raw_fd();
+            continue;
+        }
+        if unsafe { libc::dup2(stream.as_raw_fd(), fd as i32) } < 0 {
+            eprintln!("oxide: {fd}: {}", error_text(&io::Error::last_os_error()));
+            return false;
+        }
+    }
+    true
+}
+
+// A copy of one of the shell's own descriptors; EBADF if it isn't open.
+fn shell_fd(fd: usize) -> io::Result<OwnedFd> {
+    let copy = unsafe { libc::fcntl(fd as i32, libc::F_DUPFD_CLOEXEC, 0) };
+    if copy < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(copy) })
+}
+
+fn pipe() -> Option<(PipeReader, PipeWriter)> {
+    match io::pipe() {
+        Ok(ends) => Some(ends),
+        Err(e) => {
+            eprintln!("oxide: pipe: {}", error_text(&e));
+            None
+        }
+    }
+}
+
+// io::Error's Display appends " (os error N)"; shells print just the text.
+pub fn error_text(e: &io::Error) -> String {
+    let text = e.to_string();
+    match text.find(" (os error") {
+        Some(pos) => text[..pos].to_string(),
+        None => text,
+    }
+}
+
+// Returns false when a redirection couldn't be opened, in which case the
+// command must not run.
+fn redirect_command(command: &mut Command, redirects: &[Redirect], streams: Streams) -> bool {
+    let Some(mut streams) = apply_redirects(redirects, streams) else {
+        return false;
+    };
+    if let Some(fd) = streams.remove(&0) {
+        command.stdin(fd);
+    }
+    if let Some(fd) = streams.remove(&1) {
+        command.stdout(fd);
+    }
+    if let Some(fd) = streams.remove(&2) {
+        command.stderr(fd);
+    }
+    if streams.is_empty() {
+        return true;
+    }
+
+    // Command only knows about the standard three, so the rest are put in
+    // place in the child
+    unsafe {
+        command.pre_exec(move || install_streams(&streams));
+    }
+    true
+}
+
+// Puts each stream on its fd in the current process. Everything is first
+// moved to fds above 9 so that installing one can't close the source of
+// another.
+fn install_streams(streams: &Streams) -> io::Result<()> {
+    let mut moved = Vec::with_capacity(streams.len());
+    for (&target, fd) in streams {
+        let high = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 10) };
+        if high < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // closed again once it's been copied into place
+        moved.push((unsafe { OwnedFd::from_raw_fd(high) }, target as i32));
+    }
+    for (high, target) in moved {
+        if unsafe { libc::dup2(high.as_raw_fd(), target) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(text: &str) -> Token {
+        Token::Word(text.to_string())
+    }
+
+    fn op(text: &str) -> Token {
+        Token::Op(text.to_string())
+    }
+
+    #[test]
+    fn lex_splits_words_and_operators() {
+        assert_eq!(
+            tokenize("ls -l|wc  -l&&echo ok; true"),
+            [
+                word("ls"),
+                word("-l"),
+                op("|"),
+                word("wc"),
+                word("-l"),
+                op("&&"),
+                word("echo"),
+                word("ok"),
+                op(";"),
+                word("true"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_reads_redirections() {
+        assert_eq!(
+            tokenize("cat <in 2>>err >&2"),
+            [
+                word("cat"),
+                op("<"),
+                word("in"),
+                op("2>>"),
+                word("err"),
+                op(">&2"),
+            ]
+        );
+        // quoted, the 2 is a word of its own
+        assert_eq!(
+            tokenize("echo \"2\">f"),
+            [word("echo"), word("2"), op(">"), word("f")]
+        );
+    }
+
+    #[test]
+    fn lex_keeps_quoted_text_together() {
+        assert_eq!(
+            tokenize("echo 'a  b' \"c d\" e\\ f ''"),
+            [
+                word("echo"),
+                word("a  b"),
+                word("c d"),
+                word("e f"),
+                word("")
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_marks_expansions_and_patterns() {
+        let marked = tokenize("echo $HOME *.rs '$x'");
+        assert_eq!(
+            marked,
+            [
+                word("echo"),
+                word(&format!("{}HOME", expand::MARKER)),
+                word(&format!("{}{}*.rs", expand::MARKER, expand::GLOB)),
+                word("$x"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_expands_braces() {
+        assert_eq!(tokenize("a{b,c}d"), [word("abd"), word("acd")]);
+        assert_eq!(tokenize("'a{b,c}'"), [word("a{b,c}")]);
+    }
+
+    #[test]
+    fn lex_gives_spans_and_open_quotes() {
+        let (tokens, open) = lex("echo  hi");
+        let spans: Vec<Range<usize>> = tokens.into_iter().map(|(_, span)| span).collect();
+        assert_eq!(spans, [0..4, 6..8]);
+        assert_eq!(open, None);
+        assert_eq!(lex("echo 'abc").1, Some('\''));
+        assert_eq!(lex("echo \"abc").1, Some('"'));
     }
 }
@@ -1,32 +1,339 @@
 use std::{
-    env::current_dir,
-    io::{self, Write},
+    env::{self, current_dir},
+    fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+use oxide::cli::{self, Invocation, Options};
+use oxide::{
+    Script, Shell, error_text, incomplete, project_env, prompt, rc_cache, signals, terminal,
 };
 
 fn main() {
+    let mut argv = env::args();
+    let argv0 = argv.next().unwrap_or_default();
+
+    let mut options = match cli::parse_args(argv) {
+        Ok(Invocation::Run(options)) => options,
+        Ok(Invocation::Version) => {
+            println!("{}", cli::version());
+            return;
+        }
+        Ok(Invocation::Trust { dir, trusted }) => {
+            if let Err(e) = project_env::set_trust(dir.as_deref(), trusted) {
+                eprintln!("oxide: {e}");
+                process::exit(1);
+            }
+            return;
+        }
+        Ok(Invocation::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("oxide: {e}");
+            eprintln!("{}", cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    // login shells are conventionally started with a leading '-' in argv[0]
+    if argv0.starts_with('-') {
+        options.login = true;
+    }
+
+    process::exit(repl(&options, argv0));
+}
+
+// Where commands are read from.
+enum Input {
+    Stdin,
+    // a script given as an argument or with -c, and how far into it
+    // reading has got
+    Script {
+        // what errors in it are reported as coming from
+        name: String,
+        text: String,
+        pos: usize,
+        line: usize,
+    },
+}
+
+impl Input {
+    fn script(name: &str, text: String) -> Self {
+        Input::Script {
+            name: name.to_string(),
+            text,
+            pos: 0,
+            line: 0,
+        }
+    }
+
+    fn read_line(&mut self, out: &mut String) -> io::Result<usize> {
+        let Input::Script {
+            text, pos, line, ..
+        } = self
+        else {
+            return terminal::read_line(out);
+        };
+        let rest = &text[*pos..];
+        let len = rest.find('\n').map_or(rest.len(), |i| i + 1);
+        out.push_str(&rest[..len]);
+        *pos += len;
+        *line += 1;
+        Ok(len)
+    }
+
+    fn is_terminal(&self) -> bool {
+        matches!(self, Input::Stdin) && io::stdin().is_terminal()
+    }
+
+    // The number of the next line a script has, counting from 1.
+    fn line(&self) -> Option<usize> {
+        match self {
+            Input::Stdin => None,
+            Input::Script { line, .. } => Some(line + 1),
+        }
+    }
+}
+
+// Reads and runs commands until `exit`, or the end of a script, returning
+// the status to exit with. `oxide script args...` runs the script with the
+// args as its positional parameters, and `oxide -c command name args...`
+// the command, with name as $0.
+fn repl(options: &Options, argv0: String) -> i32 {
+    let mut shell = Shell::new();
+    shell.name = argv0;
+    let source = match (&options.command, options.args.split_first()) {
+        (Some(command), args) => {
+            if let Some((name, args)) = args {
+                shell.name = name.clone();
+                shell.positional = args.to_vec();
+            }
+            Input::script("-c", command.clone())
+        }
+        (None, Some((path, args))) => match fs::read_to_string(path) {
+            Ok(text) => {
+                shell.name = path.clone();
+                shell.positional = args.to_vec();
+                Input::script(path, text)
+            }
+            Err(e) => {
+                eprintln!("oxide: {path}: {}", error_text(&e));
+                return if e.kind() == io::ErrorKind::NotFound {
+                    127
+                } else {
+                    126
+                };
+            }
+        },
+        (None, None) => Input::Stdin,
+    };
+
+    let interactive =
+        matches!(source, Input::Stdin) && (options.interactive || io::stdin().is_terminal());
+    if interactive {
+        signals::install();
+    }
+    shell.interactive = interactive;
+    shell.job_control = interactive && terminal::take_control();
+
+    if options.login
+        && let Some(path) = profile_file()
+    {
+        run_startup_file(&mut shell, &path);
+    }
+    if interactive
+        && !options.norc
+        && let Some(path) = rc_file()
+    {
+        run_startup_file(&mut shell, &path);
+    }
+    run(&mut shell, source, interactive);
+
+    let status = shell.run_exit_trap();
+    if shell.job_control {
+        terminal::release();
+    }
+    status
+}
+
+// Runs a startup file's commands in the shell.
+// Runs a startup file from its cached parse where it can, and reads and
+// runs it like any other script where it can't.
+fn run_startup_file(shell: &mut Shell, path: &Path) {
+    let Some(scripts) = rc_cache::scripts(path) else {
+        run_file(shell, path);
+        return;
+    };
+    for script in scripts {
+        shell.run_traps();
+        if shell.exit_status.is_some() {
+            break;
+        }
+        shell.reap_jobs(false);
+        script.execute(shell);
+        if shell.exit_status.is_some() {
+            break;
+        }
+    }
+}
+
+fn run_file(shell: &mut Shell, path: &Path) {
+    match fs::read_to_string(path) {
+        Ok(text) => run(shell, Input::script(&path.to_string_lossy(), text), false),
+        Err(e) => eprintln!("oxide: {}: {}", path.display(), error_text(&e)),
+    }
+}
+
+// The file login shells run before anything else:
+// $XDG_CONFIG_HOME/oxide/login.oxide, or failing that ~/.oxide_profile.
+fn profile_file() -> Option<PathBuf> {
+    startup_file("login.oxide", ".oxide_profile")
+}
+
+// The startup file interactive shells run first:
+// $XDG_CONFIG_HOME/oxide/init.oxide, or failing that ~/.oxiderc.
+fn rc_file() -> Option<PathBuf> {
+    startup_file("init.oxide", ".oxiderc")
+}
+
+// `name` in oxide's config directory, or if that doesn't exist, `dotfile`
+// in the home directory.
+fn startup_file(name: &str, dotfile: &str) -> Option<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let config = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => home.as_ref().map(|home| home.join(".config")),
+    };
+    let config = config.map(|dir| dir.join("oxide").join(name));
+    let home = home.map(|home| home.join(dotfile));
+    [config, home]
+        .into_iter()
+        .flatten()
+        .find(|path| path.exists())
+}
+
+// How many Ctrl-Ds in a row `set -o ignoreeof` ignores before the shell
+// exits anyway.
+const IGNORED_EOFS: usize = 10;
+
+// Reads commands from `source` and runs them, until `exit` or the end of
+// the input.
+fn run(shell: &mut Shell, mut source: Input, interactive: bool) {
+    // shown if the working directory is deleted out from under the shell
+    let mut last_dir = String::from("?");
+    // Ctrl-Ds ignored since the last line that was read
+    let mut eofs = 0;
+
     loop {
-        let cur = current_dir().unwrap();
-        let last = cur.components().last().unwrap().as_os_str();
-        print!("{} ❯ ", last.display());
-        //println!();
-        //println!("{}", current_dir().unwrap().display());
-        //print!("$ ");
-        io::stdout().flush().unwrap();
+        shell.run_traps();
+        if shell.exit_status.is_some() {
+            break;
+        }
+        shell.reap_jobs(interactive);
+        if interactive {
+            shell.project_env.refresh();
+            shell.venv.refresh();
+            shell.universal.sync();
+            if let Ok(cur) = current_dir() {
+                terminal::set_title(&prompt::tilde_path(&cur));
+                last_dir = prompt::cwd(&cur);
+            }
+            let segments = prompt::segments();
+            let mut stdout = io::stdout();
+            // nobody is left to read the prompt, so there's no point going on
+            if write!(stdout, "{segments}{last_dir} ❯ ")
+                .and_then(|_| stdout.flush())
+                .is_err()
+            {
+                shell.exit_status = Some(128 + libc::SIGPIPE);
+                break;
+            }
+        }
 
+        let line = source.line();
         let mut input = String::new();
-        if io::stdin().read_line(&mut input).is_err() {
-            continue;
+        match source.read_line(&mut input) {
+            // Ctrl-C: abandon the line and start over on a fresh one
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                println!();
+                continue;
+            }
+            Err(e) => {
+                eprintln!("oxide: {}", error_text(&e));
+                break;
+            }
+            // Ctrl-D at the prompt, which `set -o ignoreeof` turns down a
+            // few times in a row in case it was a slip
+            Ok(0) if source.is_terminal() && shell.options.ignoreeof && eofs < IGNORED_EOFS => {
+                eofs += 1;
+                eprintln!("Use \"exit\" to leave the shell.");
+                continue;
+            }
+            // the end of a script or of piped input, or Ctrl-D
+            Ok(0) => {
+                if source.is_terminal() {
+                    eprintln!("exit");
+                }
+                break;
+            }
+            Ok(_) => eofs = 0,
         }
 
-        let tokens: Vec<&str> = input.trim().split_whitespace().collect();
-        if tokens.is_empty() {
-            continue;
+        while incomplete(&input) {
+            match read_continuation(&mut source, interactive) {
+                Some(line) => input.push_str(&line),
+                None => break,
+            }
         }
 
-        let command = oxide::ShellCommand::parse(&tokens);
+        let script = match Script::parse(&input, || read_continuation(&mut source, interactive)) {
+            Ok(script) => script,
+            Err(e) if interactive => {
+                eprintln!("oxide: {}", e.render(&input));
+                shell.status = 2;
+                continue;
+            }
+            // a script with a syntax error goes no further
+            Err(e) if let Input::Script { name, .. } = &source => {
+                let line = line.unwrap_or(1) + input[..e.offset].matches('\n').count();
+                eprintln!("oxide: {name}: line {line}: {e}");
+                shell.status = 2;
+                break;
+            }
+            Err(e) => {
+                eprintln!("oxide: {e}");
+                shell.status = 2;
+                continue;
+            }
+        };
+        if script.is_empty() {
+            continue;
+        }
 
-        if !command.execute() {
+        if interactive {
+            terminal::set_title(input.trim());
+        }
+        script.execute(shell);
+        if shell.exit_status.is_some() {
             break;
         }
     }
 }
+
+// Reads one more line of input for a command that isn't finished yet,
+// showing the "> " continuation prompt when interactive.
+fn read_continuation(source: &mut Input, interactive: bool) -> Option<String> {
+    if interactive {
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "> ").and_then(|_| stdout.flush());
+    }
+    let mut line = String::new();
+    match source.read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line),
+    }
+}
@@ -0,0 +1,130 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+// Called when an interactive `cd` is given a directory that doesn't
+// exist, returning the directory to use instead, if any. OXIDE_CD_CORRECT
+// says whether to look for one: "ask" offers it on stderr first, "on"
+// goes there and passes it to `show`; anything else, the default, leaves
+// the mistake to be reported.
+pub fn corrected_cd_target(target: &str, show: impl FnOnce(&str)) -> Option<String> {
+    let mode = env::var("OXIDE_CD_CORRECT").unwrap_or_default();
+    if mode != "ask" && mode != "on" {
+        return None;
+    }
+
+    let fixed = correct_dir(target)?;
+    if mode == "ask" {
+        let mut stderr = io::stderr();
+        write!(stderr, "cd: correct '{target}' to '{fixed}' [y/N]? ")
+            .and_then(|_| stderr.flush())
+            .ok()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer).ok()?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            return None;
+        }
+    } else {
+        show(&fixed);
+    }
+    Some(fixed)
+}
+
+// Resolves each path segment against the directories that actually exist,
+// accepting (in order of preference) a case-insensitive match, a single
+// typo, or a unique prefix. This also makes `cd d/s/p` reach
+// `docs/src/parser` when every segment is unambiguous.
+pub fn correct_dir(target: &str) -> Option<String> {
+    let path = Path::new(target);
+    let mut resolved = PathBuf::new();
+    let mut shown = Vec::new();
+
+    for component in path.components() {
+        match component {
+            Component::RootDir => resolved.push("/"),
+            Component::Normal(name) => {
+                let name = name.to_str()?;
+                let base = if resolved.as_os_str().is_empty() {
+                    PathBuf::from(".")
+                } else {
+                    resolved.clone()
+                };
+                let segment = if base.join(name).is_dir() {
+                    name.to_string()
+                } else {
+                    match_segment(&base, name)?
+                };
+                resolved.push(&segment);
+                shown.push(segment);
+            }
+            other => {
+                let s = other.as_os_str().to_str()?.to_string();
+                resolved.push(&s);
+                shown.push(s);
+            }
+        }
+    }
+
+    if !resolved.is_dir() {
+        return None;
+    }
+    let joined = shown.join("/");
+    Some(if path.is_absolute() {
+        format!("/{joined}")
+    } else {
+        joined
+    })
+}
+
+fn match_segment(base: &Path, name: &str) -> Option<String> {
+    let dirs: Vec<String> = fs::read_dir(base)
+        .ok()?
+        .flatten()
+        .filter(|e| e.path().is_dir())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .collect();
+
+    let lower = name.to_lowercase();
+    let tiers: [&dyn Fn(&str) -> bool; 3] = [
+        &|d| d.to_lowercase() == lower,
+        &|d| within_one_edit(&d.to_lowercase(), &lower),
+        &|d| d.to_lowercase().starts_with(&lower),
+    ];
+
+    for tier in tiers {
+        let found: Vec<&String> = dirs.iter().filter(|d| tier(d)).collect();
+        match found.as_slice() {
+            [only] => return Some(only.to_string()),
+            [] => continue,
+            _ => return None,
+        }
+    }
+    None
+}
+
+// True when `a` can be turned into `b` with at most one insertion,
+// deletion, substitution, or swap of two adjacent characters.
+fn within_one_edit(a: &str, b: &str) -> bool {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > 1 {
+        return false;
+    }
+
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let (a_rest, b_rest) = (&a[prefix..], &b[prefix..]);
+
+    match (a_rest.len(), b_rest.len()) {
+        (0, 0) => true,
+        (x, y) if x == y => {
+            a_rest[1..] == b_rest[1..]
+                || (x >= 2
+                    && a_rest[0] == b_rest[1]
+                    && a_rest[1] == b_rest[0]
+                    && a_rest[2..] == b_rest[2..])
+        }
+        (x, y) if x > y => a_rest[1..] == *b_rest,
+        _ => *a_rest == b_rest[1..],
+    }
+}
@@ -0,0 +1,58 @@
+use std::os::fd::{AsRawFd, OwnedFd};
+
+use crate::{Shell, Streams, Token, error_text, pipe, spawn, subshell, tokenize};
+
+// Process substitution. `<(cmd)` runs cmd with its stdout on a pipe and is
+// replaced by /dev/fd/N, the shell's read end of it, which the rest of the
+// command line inherits; `>(cmd)` is the same with the direction reversed.
+// The shell keeps its ends open until the command line is done, then
+// closes them and waits for the substituted commands.
+#[derive(Default)]
+pub struct Substitutions {
+    fds: Vec<OwnedFd>,
+    pids: Vec<i32>,
+}
+
+impl Drop for Substitutions {
+    fn drop(&mut self) {
+        // closing our ends first lets readers see EOF and writers EPIPE
+        self.fds.clear();
+        for &pid in &self.pids {
+            let _ = spawn::wait(pid);
+        }
+    }
+}
+
+pub fn start(shell: &mut Shell, tokens: Vec<Token>) -> Result<(Vec<Token>, Substitutions), String> {
+    let mut subs = Substitutions::default();
+    let mut out = Vec::with_capacity(tokens.len());
+
+    for token in tokens {
+        let Token::ProcessSub(direction, command) = token else {
+            out.push(token);
+            continue;
+        };
+
+        let inner = tokenize(&command);
+        let (reader, writer) = pipe().ok_or("cannot create pipe")?;
+        let (ours, ends): (OwnedFd, Streams) = if direction == '<' {
+            (reader.into(), Streams::from([(1, writer.into())]))
+        } else {
+            (writer.into(), Streams::from([(0, reader.into())]))
+        };
+        // run like a `$(command)`: a whole command list, expanded as it goes
+        let pid = subshell(shell, &inner, &[], ends, None).map_err(|e| error_text(&e))?;
+        subs.pids.push(pid);
+
+        out.push(Token::Word(format!("/dev/fd/{}", ours.as_raw_fd())));
+        subs.fds.push(ours);
+    }
+
+    // only now, so none of the substituted commands holds another's pipe
+    for fd in &subs.fds {
+        if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, 0) } < 0 {
+            return Err(error_text(&std::io::Error::last_os_error()));
+        }
+    }
+    Ok((out, subs))
+}
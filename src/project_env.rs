@@ -0,0 +1,154 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::env_file;
+
+pub const ENV_FILE: &str = ".envrc";
+
+// Built-in direnv: the nearest .envrc above the working directory is loaded
+// into the environment while the shell is inside that tree, and unloaded
+// again on the way out. A file is only loaded once its directory has been
+// approved with `oxide --trust`, and approval is tied to the file's contents.
+#[derive(Default)]
+pub struct ProjectEnv {
+    seen: Option<(PathBuf, u64, bool)>,
+    saved: Vec<(String, Option<OsString>)>,
+}
+
+impl ProjectEnv {
+    pub fn refresh(&mut self) {
+        let file = env::current_dir().ok().and_then(|cwd| find_env_file(&cwd));
+        let state = file.and_then(|file| {
+            let hash = fnv1a(&fs::read(&file).ok()?);
+            let trusted = is_trusted(&file, hash);
+            Some((file, hash, trusted))
+        });
+
+        if state == self.seen {
+            return;
+        }
+        self.unload();
+
+        if let Some((file, _, trusted)) = &state {
+            if *trusted {
+                self.load(file);
+            } else {
+                let dir = file.parent().unwrap_or(file);
+                eprintln!(
+                    "oxide: {} is not trusted; run `oxide --trust {}` to load it",
+                    file.display(),
+                    dir.display()
+                );
+            }
+        }
+        self.seen = state;
+    }
+
+    fn load(&mut self, file: &Path) {
+        let contents = fs::read_to_string(file).unwrap_or_default();
+        let parsed = env_file::parse(&contents);
+        for line in &parsed.skipped {
+            eprintln!(
+                "oxide: {}:{line}: not an assignment, skipped",
+                file.display()
+            );
+        }
+
+        let names: Vec<String> = parsed.vars.iter().map(|(k, _)| format!("+{k}")).collect();
+        eprintln!("oxide: loading {} ({})", file.display(), names.join(" "));
+
+        for (key, value) in parsed.vars {
+            if !self.saved.iter().any(|(k, _)| *k == key) {
+                self.saved.push((key.clone(), env::var_os(&key)));
+            }
+            // SAFETY: the shell is single-threaded
+            unsafe { env::set_var(&key, value) };
+        }
+    }
+
+    fn unload(&mut self) {
+        if let Some((file, _, true)) = &self.seen {
+            eprintln!("oxide: unloading {}", file.display());
+        }
+        for (key, old) in self.saved.drain(..).rev() {
+            // SAFETY: the shell is single-threaded
+            match old {
+                Some(value) => unsafe { env::set_var(&key, value) },
+                None => unsafe { env::remove_var(&key) },
+            }
+        }
+    }
+}
+
+fn find_env_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|d| d.join(ENV_FILE))
+        .find(|f| f.is_file())
+}
+
+fn trust_file() -> Option<PathBuf> {
+    let data = match env::var("XDG_DATA_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var("HOME").ok()?).join(".local/share"),
+    };
+    Some(data.join("oxide/trusted"))
+}
+
+// Each line of the trust file is "<hash> <path to env file>".
+fn trusted_entries() -> Vec<(u64, PathBuf)> {
+    let Some(path) = trust_file() else {
+        return Vec::new();
+    };
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let (hash, file) = line.split_once(' ')?;
+            Some((u64::from_str_radix(hash, 16).ok()?, PathBuf::from(file)))
+        })
+        .collect()
+}
+
+fn is_trusted(file: &Path, hash: u64) -> bool {
+    trusted_entries()
+        .iter()
+        .any(|(h, f)| *h == hash && f == file)
+}
+
+// Implements `oxide --trust [dir]` and `oxide --untrust [dir]`.
+pub fn set_trust(dir: Option<&str>, trusted: bool) -> Result<(), String> {
+    let dir = match dir {
+        Some(dir) => PathBuf::from(dir),
+        None => env::current_dir().map_err(|e| e.to_string())?,
+    };
+    let dir = fs::canonicalize(&dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    let file = dir.join(ENV_FILE);
+    let trust_file = trust_file().ok_or("HOME is not set")?;
+
+    let mut entries: Vec<(u64, PathBuf)> = trusted_entries()
+        .into_iter()
+        .filter(|(_, f)| *f != file)
+        .collect();
+    if trusted {
+        let contents = fs::read(&file).map_err(|e| format!("{}: {e}", file.display()))?;
+        entries.push((fnv1a(&contents), file));
+    }
+
+    if let Some(parent) = trust_file.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let mut out = fs::File::create(&trust_file).map_err(|e| e.to_string())?;
+    for (hash, file) in entries {
+        writeln!(out, "{hash:016x} {}", file.display()).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x100000001b3)
+    })
+}
@@ -0,0 +1,144 @@
+use std::env;
+use std::fs;
+use std::path::{Component, Path};
+
+use crate::terminal;
+
+// Context shown before the directory: active Python virtualenv, the Node
+// version nvm selected, the Rust toolchain pinned for the project, and the
+// tmux session when enabled. Each segment only appears when it applies.
+pub fn segments() -> String {
+    let mut parts = Vec::new();
+
+    if let Some(venv) = python_venv() {
+        parts.push(format!("({venv})"));
+    }
+    if let Some(version) = node_version() {
+        parts.push(format!("node:{version}"));
+    }
+    if let Some(toolchain) = rust_toolchain() {
+        parts.push(format!("rust:{toolchain}"));
+    }
+    if let Some(tmux) = terminal::tmux_segment() {
+        parts.push(format!("[{tmux}]"));
+    }
+
+    parts.iter().map(|p| format!("{p} ")).collect()
+}
+
+fn python_venv() -> Option<String> {
+    let venv = env::var("VIRTUAL_ENV").ok()?;
+    let path = Path::new(&venv);
+    let name = path.file_name()?.to_string_lossy();
+    // `.venv` says nothing; name it after the project instead
+    if name.starts_with('.') {
+        let project = path.parent()?.file_name()?.to_string_lossy();
+        return Some(project.into_owned());
+    }
+    Some(name.into_owned())
+}
+
+// nvm puts the selected version's bin directory in NVM_BIN, e.g.
+// ~/.nvm/versions/node/v20.11.0/bin.
+fn node_version() -> Option<String> {
+    let bin = env::var("NVM_BIN").ok()?;
+    let version = Path::new(&bin).parent()?.file_name()?.to_string_lossy();
+    version.starts_with('v').then(|| version.into_owned())
+}
+
+fn rust_toolchain() -> Option<String> {
+    if let Ok(toolchain) = env::var("RUSTUP_TOOLCHAIN") {
+        return Some(toolchain);
+    }
+
+    let cwd = env::current_dir().ok()?;
+    for dir in cwd.ancestors() {
+        if let Ok(contents) = fs::read_to_string(dir.join("rust-toolchain.toml")) {
+            return contents.lines().find_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                (key.trim() == "channel").then(|| value.trim().trim_matches('"').to_string())
+            });
+        }
+        if let Ok(contents) = fs::read_to_string(dir.join("rust-toolchain")) {
+            return contents.lines().next().map(|l| l.trim().to_string());
+        }
+    }
+    None
+}
+
+// The working directory as shown in the prompt, chosen with
+// OXIDE_PROMPT_PATH:
+//   last (default)  the final component          src
+//   last:N          the final N components       oxide/src
+//   short           every parent abbreviated     ~/c/o/src
+//   git             relative to the repo root    oxide:src/builtins
+pub fn cwd(path: &Path) -> String {
+    let strategy = env::var("OXIDE_PROMPT_PATH").unwrap_or_default();
+
+    match strategy.as_str() {
+        "short" => shorten_parents(&tilde_path(path)),
+        "git" => git_relative(path).unwrap_or_else(|| last_components(path, 1)),
+        s => {
+            let n = s
+                .strip_prefix("last:")
+                .and_then(|n| n.parse().ok())
+                .filter(|&n| n > 0)
+                .unwrap_or(1);
+            last_components(path, n)
+        }
+    }
+}
+
+pub fn tilde_path(path: &Path) -> String {
+    let display = path.display().to_string();
+    match env::var("HOME") {
+        Ok(home) if !home.is_empty() && path.starts_with(&home) => {
+            format!("~{}", &display[home.len()..])
+        }
+        _ => display,
+    }
+}
+
+fn last_components(path: &Path, n: usize) -> String {
+    let names: Vec<String> = path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect();
+    if names.is_empty() {
+        return "/".to_string();
+    }
+    names[names.len().saturating_sub(n)..].join("/")
+}
+
+fn shorten_parents(path: &str) -> String {
+    let parts: Vec<&str> = path.split('/').collect();
+    let last = parts.len() - 1;
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            if i == last || part.is_empty() || *part == "~" {
+                part.to_string()
+            } else {
+                // keep a leading dot so hidden directories stay recognisable
+                let take = if part.starts_with('.') { 2 } else { 1 };
+                part.chars().take(take).collect()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn git_relative(path: &Path) -> Option<String> {
+    let root = path.ancestors().find(|dir| dir.join(".git").exists())?;
+    let name = root.file_name()?.to_string_lossy();
+    let rest = path.strip_prefix(root).ok()?;
+    if rest.as_os_str().is_empty() {
+        Some(name.into_owned())
+    } else {
+        Some(format!("{name}:{}", rest.display()))
+    }
+}
@@ -0,0 +1,130 @@
+use std::env;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::{Script, Token, incomplete};
+
+// Startup files parsed ahead of time. The first shell to run one writes
+// its commands, already tokenized, to a file under ~/.cache/oxide along
+// with the startup file's modification time and size; later shells read
+// them back from there instead of tokenizing the file again, until either
+// of those changes.
+
+const VERSION: &str = "oxide rc cache 1";
+
+// The commands in the startup file at `path`, one script for each, from
+// the cache if the file hasn't changed since it was parsed, or parsed now
+// and cached for next time. None if the file can't be read or has a
+// syntax error, which running it the usual way reports.
+pub fn scripts(path: &Path) -> Option<Vec<Script>> {
+    let stamp = stamp(path)?;
+    let cache = cache_file(path);
+    if let Some(scripts) = cache.as_ref().and_then(|cache| load(cache, path, &stamp)) {
+        return Some(scripts);
+    }
+
+    let scripts = parse(&fs::read_to_string(path).ok()?)?;
+    if let Some(cache) = cache {
+        let _ = store(&cache, path, &stamp, &scripts);
+    }
+    Some(scripts)
+}
+
+// Splits the text into commands the way the shell reads a script: a line
+// at a time, and more lines while a command isn't finished.
+fn parse(text: &str) -> Option<Vec<Script>> {
+    let mut lines = text.split_inclusive('\n');
+    let mut scripts = Vec::new();
+    while let Some(line) = lines.next() {
+        let mut input = line.to_string();
+        while incomplete(&input) {
+            match lines.next() {
+                Some(line) => input.push_str(line),
+                None => break,
+            }
+        }
+        let script = Script::parse(&input, || lines.next().map(str::to_string)).ok()?;
+        if !script.is_empty() {
+            scripts.push(script);
+        }
+    }
+    Some(scripts)
+}
+
+fn stamp(path: &Path) -> Option<String> {
+    let meta = fs::metadata(path).ok()?;
+    let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("{} {}", modified.as_nanos(), meta.len()))
+}
+
+// One cache file for each startup file, named for a hash of its path.
+fn cache_file(path: &Path) -> Option<PathBuf> {
+    let cache = match env::var("XDG_CACHE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var("HOME").ok()?).join(".cache"),
+    };
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    Some(cache.join(format!("oxide/rc-{:016x}", hasher.finish())))
+}
+
+// The header is the version, the startup file's path and its stamp, a
+// line each. After it, `S` starts each script, and each of its tokens is
+// a letter for its kind, the length of its text, a `:` and the text.
+fn store(cache: &Path, path: &Path, stamp: &str, scripts: &[Script]) -> io::Result<()> {
+    let mut out = format!("{VERSION}\n{}\n{stamp}\n", path.display());
+    for script in scripts {
+        out.push_str("S\n");
+        for token in &script.tokens {
+            let (kind, text) = match token {
+                Token::Word(text) => ("W".to_string(), text),
+                Token::Op(text) => ("O".to_string(), text),
+                Token::ProcessSub(c, text) => (format!("P{c}"), text),
+            };
+            out.push_str(&format!("{kind}{}:{text}\n", text.len()));
+        }
+    }
+
+    if let Some(dir) = cache.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = cache.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp, out)?;
+    fs::rename(&tmp, cache)
+}
+
+// The cached scripts, if they were parsed from the file as it is now.
+// Anything unexpected in the cache makes it a miss.
+fn load(cache: &Path, path: &Path, stamp: &str) -> Option<Vec<Script>> {
+    let text = fs::read_to_string(cache).ok()?;
+    let header = format!("{VERSION}\n{}\n{stamp}\n", path.display());
+    let mut rest = text.strip_prefix(&header)?;
+
+    let mut scripts: Vec<Script> = Vec::new();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("S\n") {
+            scripts.push(Script { tokens: Vec::new() });
+            rest = after;
+            continue;
+        }
+        let mut chars = rest.chars();
+        let kind = chars.next()?;
+        let sub = if kind == 'P' { chars.next() } else { None };
+        let (len, after) = chars.as_str().split_once(':')?;
+        let len: usize = len.parse().ok()?;
+        let text = after.get(..len)?.to_string();
+        rest = after.get(len..)?.strip_prefix('\n')?;
+
+        let token = match (kind, sub) {
+            ('W', _) => Token::Word(text),
+            ('O', _) => Token::Op(text),
+            ('P', Some(c)) => Token::ProcessSub(c, text),
+            _ => return None,
+        };
+        scripts.last_mut()?.tokens.push(token);
+    }
+    Some(scripts)
+}
@@ -0,0 +1,101 @@
+// Signal handling for interactive shells.
+//
+// The shell catches Ctrl-C and Ctrl-Z with a handler that does nothing
+// rather than ignoring them: a caught signal reverts to its default action
+// in an exec'd child, an ignored one would stay ignored, and the children
+// are the ones that should be interrupted or stopped.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+// Signals with a `trap` command that have arrived but not been handled.
+static PENDING: AtomicU64 = AtomicU64::new(0);
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+// Set by Ctrl-C reaching the shell itself, which it does while only
+// builtins are running.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn ignore_signal(_: libc::c_int) {}
+
+extern "C" fn interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn record(signal: libc::c_int) {
+    PENDING.fetch_or(1 << signal, Ordering::SeqCst);
+}
+
+pub fn install() {
+    INSTALLED.store(true, Ordering::SeqCst);
+    catch(libc::SIGTSTP, true);
+    // not restarted, so Ctrl-C at the prompt interrupts reading the line
+    catch_interrupt();
+}
+
+// Undoes install, for a subshell: Ctrl-C and Ctrl-Z act on it normally.
+pub fn uninstall() {
+    INSTALLED.store(false, Ordering::SeqCst);
+    set_handler(libc::SIGTSTP, libc::SIG_DFL, true);
+    set_handler(libc::SIGINT, libc::SIG_DFL, true);
+}
+
+// Records the signal for the shell to run its trap command. Like Ctrl-C,
+// it interrupts reading a line, so the trap runs before the next prompt.
+pub fn trap(signal: libc::c_int) {
+    set_handler(signal, record as *const () as libc::sighandler_t, false);
+}
+
+pub fn ignore(signal: libc::c_int) {
+    set_handler(signal, libc::SIG_IGN, true);
+}
+
+// Puts the signal back the way it was before any trap was set on it.
+pub fn restore(signal: libc::c_int) {
+    if signal == libc::SIGINT && INSTALLED.load(Ordering::SeqCst) {
+        catch_interrupt();
+    } else {
+        set_handler(signal, libc::SIG_DFL, true);
+    }
+}
+
+// The trapped signals received since the last call, in signal order.
+pub fn take_pending() -> Vec<libc::c_int> {
+    let pending = PENDING.swap(0, Ordering::SeqCst);
+    (1..64).filter(|sig| pending & (1 << sig) != 0).collect()
+}
+
+// The first trapped signal waiting to be handled, leaving it waiting.
+pub fn pending() -> Option<libc::c_int> {
+    let pending = PENDING.load(Ordering::SeqCst);
+    (1..64).find(|sig| pending & (1 << sig) != 0)
+}
+
+// Whether Ctrl-C has reached the shell since the last call.
+pub fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+fn catch_interrupt() {
+    set_handler(
+        libc::SIGINT,
+        interrupt as *const () as libc::sighandler_t,
+        false,
+    );
+}
+
+fn catch(signal: libc::c_int, restart: bool) {
+    set_handler(
+        signal,
+        ignore_signal as *const () as libc::sighandler_t,
+        restart,
+    );
+}
+
+fn set_handler(signal: libc::c_int, handler: libc::sighandler_t, restart: bool) {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler;
+        action.sa_flags = if restart { libc::SA_RESTART } else { 0 };
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, std::ptr::null_mut());
+    }
+}
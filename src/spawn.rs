@@ -0,0 +1,95 @@
+use std::ffi::{CString, c_char};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+
+use crate::jobs::decode_status;
+
+unsafe extern "C" {
+    static environ: *const *mut c_char;
+}
+
+// Fast path for the common case of an external command with no
+// redirections: posix_spawn the already-resolved executable directly,
+// skipping Command's builder state and post-fork bookkeeping. `pgroup` is
+// the process group to put it in, as for Command::process_group.
+pub fn spawn(path: &Path, argv: &[&str], pgroup: Option<libc::pid_t>) -> io::Result<libc::pid_t> {
+    let program = CString::new(path.as_os_str().as_bytes())?;
+    let args = argv
+        .iter()
+        .map(|a| CString::new(*a))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut arg_ptrs: Vec<*mut c_char> = args.iter().map(|a| a.as_ptr() as *mut c_char).collect();
+    arg_ptrs.push(ptr::null_mut());
+
+    // the shell ignores SIGPIPE so it can handle EPIPE itself; children
+    // must get the default back or `yes | head` never terminates
+    let mut attr: libc::posix_spawnattr_t = unsafe { std::mem::zeroed() };
+    let mut pid = 0;
+    let rc = unsafe {
+        let mut sigdefault: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut sigdefault);
+        libc::sigaddset(&mut sigdefault, libc::SIGPIPE);
+        libc::posix_spawnattr_init(&mut attr);
+        libc::posix_spawnattr_setsigdefault(&mut attr, &sigdefault);
+        let mut flags = libc::POSIX_SPAWN_SETSIGDEF;
+        if let Some(pgroup) = pgroup {
+            libc::posix_spawnattr_setpgroup(&mut attr, pgroup);
+            flags |= libc::POSIX_SPAWN_SETPGROUP;
+        }
+        libc::posix_spawnattr_setflags(&mut attr, flags as libc::c_short);
+
+        let rc = libc::posix_spawn(
+            &mut pid,
+            program.as_ptr(),
+            ptr::null(),
+            &attr,
+            arg_ptrs.as_ptr(),
+            environ,
+        );
+        libc::posix_spawnattr_destroy(&mut attr);
+        rc
+    };
+    if rc != 0 {
+        return Err(io::Error::from_raw_os_error(rc));
+    }
+    Ok(pid)
+}
+
+pub fn wait(pid: libc::pid_t) -> io::Result<i32> {
+    let mut raw = 0;
+    loop {
+        if unsafe { libc::waitpid(pid, &mut raw, 0) } != -1 {
+            return Ok(decode_status(raw));
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+// Like wait, but also returns when the process is stopped (Ctrl-Z), in
+// which case the result is None.
+pub fn wait_foreground(pid: libc::pid_t) -> io::Result<Option<i32>> {
+    let mut raw = 0;
+    loop {
+        if unsafe { libc::waitpid(pid, &mut raw, libc::WUNTRACED) } != -1 {
+            if libc::WIFSTOPPED(raw) {
+                // it touched the terminal before the shell had handed it
+                // over; it has it now, so it can carry on
+                if matches!(libc::WSTOPSIG(raw), libc::SIGTTIN | libc::SIGTTOU) {
+                    unsafe { libc::kill(pid, libc::SIGCONT) };
+                    continue;
+                }
+                return Ok(None);
+            }
+            return Ok(Some(decode_status(raw)));
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
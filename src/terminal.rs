@@ -0,0 +1,160 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::process::Command;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Multiplexer {
+    Tmux,
+    Screen,
+}
+
+pub fn multiplexer() -> Option<Multiplexer> {
+    if env::var_os("TMUX").is_some() {
+        Some(Multiplexer::Tmux)
+    } else if env::var_os("STY").is_some()
+        || env::var("TERM").is_ok_and(|t| t.starts_with("screen"))
+    {
+        Some(Multiplexer::Screen)
+    } else {
+        None
+    }
+}
+
+// Sets the terminal (or multiplexer pane/window) title. Inside tmux the
+// plain OSC 2 sequence sets the pane title, which tmux forwards to the
+// outer terminal itself when `set-titles` is on. screen only understands
+// its own window-title sequence, so the outer terminal's title has to be
+// wrapped in a DCS passthrough.
+pub fn set_title(title: &str) {
+    if env::var("OXIDE_TITLE").is_ok_and(|v| v == "off") || !io::stdout().is_terminal() {
+        return;
+    }
+
+    let title: String = title.chars().filter(|c| !c.is_control()).collect();
+    let sequence = match multiplexer() {
+        None => format!("\x1b]2;{title}\x07"),
+        Some(Multiplexer::Tmux) => format!("\x1b]2;{title}\x1b\\"),
+        Some(Multiplexer::Screen) => {
+            format!("\x1bk{title}\x1b\\\x1bP\x1b]2;{title}\x07\x1b\\")
+        }
+    };
+
+    let mut stdout = io::stdout();
+    let _ = stdout
+        .write_all(sequence.as_bytes())
+        .and_then(|_| stdout.flush());
+}
+
+// `session:window` for the prompt when OXIDE_PROMPT_TMUX is set and the
+// shell is running inside tmux.
+pub fn tmux_segment() -> Option<String> {
+    if multiplexer() != Some(Multiplexer::Tmux) || env::var_os("OXIDE_PROMPT_TMUX").is_none() {
+        return None;
+    }
+
+    let output = Command::new("tmux")
+        .args(["display-message", "-p", "#S:#I"])
+        .output()
+        .ok()?;
+    let segment = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !segment.is_empty()).then_some(segment)
+}
+
+// Reads a line from stdin. On a terminal (canonical mode, so each read(2)
+// returns at most one line) this calls read(2) directly: std's read_line
+// retries reads interrupted by a signal, which would leave Ctrl-C at the
+// prompt doing nothing, while here it comes back as Interrupted.
+pub fn read_line(line: &mut String) -> io::Result<usize> {
+    if !io::stdin().is_terminal() {
+        return io::stdin().read_line(line);
+    }
+
+    let mut bytes = Vec::new();
+    let mut buf = [0u8; 4096];
+    while !bytes.ends_with(b"\n") {
+        let n = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if n == 0 {
+            break;
+        }
+        bytes.extend_from_slice(&buf[..n as usize]);
+    }
+    line.push_str(&String::from_utf8_lossy(&bytes));
+    Ok(bytes.len())
+}
+
+// The process group the shell was started in, given the terminal back
+// when it exits.
+static ORIGINAL_PGRP: AtomicI32 = AtomicI32::new(0);
+// The shell's own terminal modes, put back whenever it takes the terminal
+// back from a job, which may have left it in raw mode.
+static SHELL_MODES: OnceLock<libc::termios> = OnceLock::new();
+
+// Sets the shell up for job control: waits (stopped) until it's in the
+// foreground if it was started in the background, then moves it into a
+// process group of its own that owns the terminal. Returns whether job
+// control is possible at all.
+pub fn take_control() -> bool {
+    if !io::stdin().is_terminal() {
+        return false;
+    }
+
+    unsafe {
+        while libc::tcgetpgrp(libc::STDIN_FILENO) != libc::getpgrp() {
+            libc::kill(-libc::getpgrp(), libc::SIGTTIN);
+        }
+        ORIGINAL_PGRP.store(libc::getpgrp(), Ordering::SeqCst);
+
+        let pid = libc::getpid();
+        if libc::getpgrp() != pid && libc::setpgid(0, 0) < 0 {
+            return false;
+        }
+        let mut modes: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut modes) == 0 {
+            let _ = SHELL_MODES.set(modes);
+        }
+        give_terminal(pid);
+    }
+    true
+}
+
+// Makes `pgid` the terminal's foreground process group: the one that can
+// read from it, and that Ctrl-C and Ctrl-Z signal.
+pub fn give_terminal(pgid: libc::pid_t) {
+    unsafe {
+        // taking the terminal back from a job is done from the background,
+        // which would otherwise stop the shell with SIGTTOU
+        let mut block: libc::sigset_t = std::mem::zeroed();
+        let mut old: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut block);
+        libc::sigaddset(&mut block, libc::SIGTTOU);
+        libc::sigprocmask(libc::SIG_BLOCK, &block, &mut old);
+        libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+        libc::sigprocmask(libc::SIG_SETMASK, &old, std::ptr::null_mut());
+    }
+}
+
+// Takes the terminal back once the foreground job has finished or stopped.
+pub fn reclaim() {
+    give_terminal(unsafe { libc::getpgrp() });
+    if let Some(modes) = SHELL_MODES.get() {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, modes) };
+    }
+}
+
+// Undoes take_control, for when the shell exits or execs.
+pub fn release() {
+    let original = ORIGINAL_PGRP.load(Ordering::SeqCst);
+    if original == 0 {
+        return;
+    }
+    unsafe {
+        if libc::setpgid(0, original) == 0 {
+            give_terminal(original);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+
+use crate::signals;
+
+// The pseudo-signal the EXIT trap is registered under, as in `trap - 0`.
+pub const EXIT: i32 = 0;
+
+// The signals `trap` accepts by name; any of them also works with a SIG
+// prefix, in lower case, or by number.
+pub const SIGNALS: &[(&str, i32)] = &[
+    ("EXIT", EXIT),
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("WINCH", libc::SIGWINCH),
+];
+
+pub fn signal_number(spec: &str) -> Option<i32> {
+    if let Ok(n) = spec.parse::<i32>() {
+        return SIGNALS.iter().any(|&(_, sig)| sig == n).then_some(n);
+    }
+    let upper = spec.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    SIGNALS
+        .iter()
+        .find(|&&(n, _)| n == name)
+        .map(|&(_, sig)| sig)
+}
+
+pub fn signal_name(signal: i32) -> &'static str {
+    SIGNALS
+        .iter()
+        .find(|&&(_, sig)| sig == signal)
+        .map_or("?", |&(name, _)| name)
+}
+
+// The commands registered with `trap`, by signal. An empty command means
+// the signal is ignored.
+#[derive(Default)]
+pub struct Traps {
+    actions: BTreeMap<i32, String>,
+}
+
+impl Traps {
+    pub fn set(&mut self, signal: i32, action: &str) {
+        if signal != EXIT {
+            if action.is_empty() {
+                signals::ignore(signal);
+            } else {
+                signals::trap(signal);
+            }
+        }
+        self.actions.insert(signal, action.to_string());
+    }
+
+    pub fn reset(&mut self, signal: i32) {
+        if self.actions.remove(&signal).is_some() && signal != EXIT {
+            signals::restore(signal);
+        }
+    }
+
+    pub fn get(&self, signal: i32) -> Option<&str> {
+        self.actions.get(&signal).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (i32, &str)> {
+        self.actions
+            .iter()
+            .map(|(&sig, action)| (sig, action.as_str()))
+    }
+
+    // Traps aren't inherited by subshells, but ignored signals are.
+    pub fn reset_for_subshell(&mut self) {
+        let trapped: Vec<i32> = self
+            .iter()
+            .filter(|&(_, action)| !action.is_empty())
+            .map(|(signal, _)| signal)
+            .collect();
+        for signal in trapped {
+            self.reset(signal);
+        }
+        for &signal in self.actions.keys() {
+            if signal != EXIT {
+                signals::ignore(signal);
+            }
+        }
+    }
+
+    // The EXIT trap is only ever run once, even if it calls `exit` itself.
+    pub fn take_exit(&mut self) -> Option<String> {
+        self.actions.remove(&EXIT)
+    }
+}
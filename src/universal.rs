@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::env_file;
+
+// Fish-style universal variables (`set -U`): kept in a state file shared by
+// every oxide instance and exported into each one's environment. Writers
+// replace the file atomically, and each shell re-reads it before showing a
+// prompt whenever its modification time changed, so a value set in one
+// terminal shows up in the others at their next prompt.
+#[derive(Default)]
+pub struct UniversalVars {
+    stamp: Option<SystemTime>,
+    vars: BTreeMap<String, String>,
+}
+
+impl UniversalVars {
+    pub fn vars(&self) -> &BTreeMap<String, String> {
+        &self.vars
+    }
+
+    pub fn sync(&mut self) {
+        let Some(file) = state_file() else {
+            return;
+        };
+        let stamp = fs::metadata(&file).and_then(|m| m.modified()).ok();
+        if stamp == self.stamp {
+            return;
+        }
+        self.stamp = stamp;
+
+        let contents = fs::read_to_string(&file).unwrap_or_default();
+        let fresh: BTreeMap<String, String> = env_file::parse(&contents).vars.into_iter().collect();
+
+        for name in self.vars.keys() {
+            if !fresh.contains_key(name) {
+                // SAFETY: the shell is single-threaded
+                unsafe { env::remove_var(name) };
+            }
+        }
+        for (name, value) in &fresh {
+            // SAFETY: the shell is single-threaded
+            unsafe { env::set_var(name, value) };
+        }
+        self.vars = fresh;
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> io::Result<()> {
+        self.sync();
+        self.vars.insert(name.to_string(), value.to_string());
+        // SAFETY: the shell is single-threaded
+        unsafe { env::set_var(name, value) };
+        self.save()
+    }
+
+    pub fn erase(&mut self, name: &str) -> io::Result<bool> {
+        self.sync();
+        if self.vars.remove(name).is_none() {
+            return Ok(false);
+        }
+        // SAFETY: the shell is single-threaded
+        unsafe { env::remove_var(name) };
+        self.save().map(|_| true)
+    }
+
+    fn save(&mut self) -> io::Result<()> {
+        let file = state_file().ok_or_else(|| io::Error::other("HOME is not set"))?;
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let tmp = file.with_extension(format!("tmp{}", std::process::id()));
+        let mut out = fs::File::create(&tmp)?;
+        for (name, value) in &self.vars {
+            let escaped = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            writeln!(out, "{name}=\"{escaped}\"")?;
+        }
+        drop(out);
+        fs::rename(&tmp, &file)?;
+
+        self.stamp = fs::metadata(&file).and_then(|m| m.modified()).ok();
+        Ok(())
+    }
+}
+
+fn state_file() -> Option<PathBuf> {
+    let config = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var("HOME").ok()?).join(".config"),
+    };
+    Some(config.join("oxide/universal_variables"))
+}
@@ -0,0 +1,201 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::ffi::OsString;
+
+use crate::env_file::is_name;
+
+// Shell variables. Exported ones live in the process environment, which
+// is what the commands the shell starts inherit; the rest are kept here,
+// seen only by the shell's own expansions.
+#[derive(Default)]
+pub struct Variables {
+    local: BTreeMap<String, String>,
+    // names given to `export` before they had a value
+    pending: BTreeSet<String>,
+    // the variables each running function made `local`, with what they
+    // were before it did, innermost function last
+    scopes: Vec<Vec<(String, Previous)>>,
+}
+
+// A variable as it was before `local` hid it.
+struct Previous {
+    local: Option<String>,
+    exported: Option<OsString>,
+    pending: bool,
+}
+
+impl Variables {
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.local
+            .get(name)
+            .cloned()
+            .or_else(|| env::var(name).ok())
+    }
+
+    // `NAME=value`: the variable stays exported if it already was.
+    pub fn set(&mut self, name: &str, value: &str) {
+        if self.pending.remove(name) || env::var_os(name).is_some() {
+            // SAFETY: the shell is single-threaded
+            unsafe { env::set_var(name, value) };
+        } else {
+            self.local.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    // `export NAME=value`, or `export NAME` to export the variable's
+    // current value (or, if it has none yet, the first one it gets).
+    pub fn export(&mut self, name: &str, value: Option<&str>) {
+        let local = self.local.remove(name);
+        match value.map(str::to_string).or(local) {
+            // SAFETY: the shell is single-threaded
+            Some(value) => unsafe { env::set_var(name, value) },
+            None if env::var_os(name).is_none() => {
+                self.pending.insert(name.to_string());
+            }
+            None => {}
+        }
+    }
+
+    // `export -n NAME`: the variable keeps its value but is no longer
+    // passed on to commands.
+    pub fn unexport(&mut self, name: &str) {
+        self.pending.remove(name);
+        if let Ok(value) = env::var(name) {
+            // SAFETY: the shell is single-threaded
+            unsafe { env::remove_var(name) };
+            self.local.insert(name.to_string(), value);
+        }
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.local.remove(name);
+        self.pending.remove(name);
+        // SAFETY: the shell is single-threaded
+        unsafe { env::remove_var(name) };
+    }
+
+    // Starts a function's scope, for `local` to declare variables in.
+    pub fn push_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    // Ends the innermost scope, giving each variable declared in it back
+    // what it was before.
+    pub fn pop_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        for (name, previous) in scope {
+            self.unset(&name);
+            if let Some(value) = previous.local {
+                self.local.insert(name.clone(), value);
+            }
+            if let Some(value) = previous.exported {
+                // SAFETY: the shell is single-threaded
+                unsafe { env::set_var(&name, value) };
+            }
+            if previous.pending {
+                self.pending.insert(name);
+            }
+        }
+    }
+
+    // `local NAME[=value]`: a variable of the innermost scope, which isn't
+    // exported and has no value until it's given one. False if no function
+    // is running.
+    pub fn declare_local(&mut self, name: &str, value: Option<&str>) -> bool {
+        let Some(scope) = self.scopes.last() else {
+            return false;
+        };
+        if !scope.iter().any(|(n, _)| n == name) {
+            let previous = Previous {
+                local: self.local.get(name).cloned(),
+                exported: env::var_os(name),
+                pending: self.pending.contains(name),
+            };
+            self.unset(name);
+            if let Some(scope) = self.scopes.last_mut() {
+                scope.push((name.to_string(), previous));
+            }
+        }
+        if let Some(value) = value {
+            self.set(name, value);
+        }
+        true
+    }
+
+    // The innermost scope's variables that have values, in the order they
+    // were declared.
+    pub fn locals(&self) -> Vec<(String, String)> {
+        let Some(scope) = self.scopes.last() else {
+            return Vec::new();
+        };
+        scope
+            .iter()
+            .filter_map(|(name, _)| Some((name.clone(), self.get(name)?)))
+            .collect()
+    }
+
+    // Every variable, exported or not, by name.
+    pub fn all(&self) -> Vec<(String, String)> {
+        let mut vars = self.exported();
+        vars.extend(self.local.iter().map(|(k, v)| (k.clone(), v.clone())));
+        vars.sort();
+        vars
+    }
+
+    // Exported variables by name. Any that aren't valid UTF-8, or whose
+    // names no `$` could refer to (such as exported functions), are left
+    // out.
+    pub fn exported(&self) -> Vec<(String, String)> {
+        let mut vars: Vec<(String, String)> = env::vars_os()
+            .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+            .filter(|(k, _)| is_name(k))
+            .collect();
+        vars.sort();
+        vars
+    }
+}
+
+// What a command's `NAME=value` prefixes replaced in the environment.
+pub struct Saved(Vec<(String, Option<OsString>)>);
+
+// Puts `NAME=value` prefixes in the environment for the command they come
+// before, which is how it (and anything it starts) sees them; restore
+// takes them out again once it has run.
+pub fn push_prefixes(assignments: &[(&str, &str)]) -> Saved {
+    let mut saved = Vec::new();
+    for &(name, value) in assignments {
+        saved.push((name.to_string(), env::var_os(name)));
+        // SAFETY: the shell is single-threaded
+        unsafe { env::set_var(name, value) };
+    }
+    Saved(saved)
+}
+
+pub fn restore(saved: Saved) {
+    // in reverse, in case a name was assigned twice
+    for (name, value) in saved.0.into_iter().rev() {
+        // SAFETY: the shell is single-threaded
+        unsafe {
+            match value {
+                Some(value) => env::set_var(&name, value),
+                None => env::remove_var(&name),
+            }
+        }
+    }
+}
+
+// A value in double quotes, escaped so the shell reads it back unchanged.
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,65 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+// With OXIDE_AUTO_VENV set, entering a project whose root holds a `.venv`
+// activates it (VIRTUAL_ENV plus its bin directory at the front of PATH)
+// and leaving the project deactivates it again. Virtualenvs the user
+// activated by hand are left alone.
+#[derive(Default)]
+pub struct AutoVenv {
+    active: Option<PathBuf>,
+}
+
+impl AutoVenv {
+    pub fn refresh(&mut self) {
+        if env::var_os("OXIDE_AUTO_VENV").is_none() {
+            return;
+        }
+
+        let found = env::current_dir().ok().and_then(|cwd| {
+            cwd.ancestors()
+                .map(|dir| dir.join(".venv"))
+                .find(|venv| venv.join("pyvenv.cfg").is_file())
+        });
+        if found == self.active {
+            return;
+        }
+
+        if let Some(old) = self.active.take() {
+            deactivate(&old);
+        }
+        if let Some(venv) = found
+            && env::var_os("VIRTUAL_ENV").is_none()
+        {
+            activate(&venv);
+            self.active = Some(venv);
+        }
+    }
+}
+
+fn activate(venv: &Path) {
+    let mut paths = vec![venv.join("bin")];
+    if let Some(path) = env::var_os("PATH") {
+        paths.extend(env::split_paths(&path));
+    }
+    let path = env::join_paths(paths).unwrap_or_default();
+    // SAFETY: the shell is single-threaded
+    unsafe {
+        env::set_var("VIRTUAL_ENV", venv);
+        env::set_var("PATH", path);
+    }
+}
+
+fn deactivate(venv: &Path) {
+    let bin = venv.join("bin");
+    let path = env::var_os("PATH").unwrap_or_default();
+    let paths: Vec<PathBuf> = env::split_paths(&path).filter(|p| *p != bin).collect();
+    let path = env::join_paths(paths).unwrap_or_default();
+    // SAFETY: the shell is single-threaded
+    unsafe {
+        env::set_var("PATH", path);
+        if env::var_os("VIRTUAL_ENV").is_some_and(|v| Path::new(&v) == venv) {
+            env::remove_var("VIRTUAL_ENV");
+        }
+    }
+}